@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS "button-answers" (
+    id SERIAL PRIMARY KEY,
+    num VARCHAR NOT NULL,
+    mensagem TEXT NOT NULL,
+    resposta_cliente VARCHAR NOT NULL,
+    tipo VARCHAR,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Tables created from the old README snippet are missing the column insert_log writes.
+ALTER TABLE "button-answers" ADD COLUMN IF NOT EXISTS tipo VARCHAR;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS "button-answers-shadow" (
+    id SERIAL PRIMARY KEY,
+    num VARCHAR NOT NULL,
+    source VARCHAR NOT NULL,
+    source_name VARCHAR NOT NULL,
+    resposta_cliente VARCHAR NOT NULL,
+    tipo VARCHAR NOT NULL,
+    mensagem_enviada TEXT,
+    mensagem TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
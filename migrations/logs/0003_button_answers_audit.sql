@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS "button-answers-audit" (
+    id SERIAL PRIMARY KEY,
+    num VARCHAR,
+    source VARCHAR,
+    message_id VARCHAR,
+    context_id VARCHAR,
+    gs_id VARCHAR,
+    meta_msg_id VARCHAR,
+    message_timestamp VARCHAR,
+    button_text VARCHAR,
+    button_payload VARCHAR,
+    tipo VARCHAR,
+    provider_response TEXT,
+    duration_ms BIGINT NOT NULL,
+    error TEXT,
+    payload TEXT,
+    payload_gz BYTEA,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS "button-answers-audit_num_idx" ON "button-answers-audit" (num);
@@ -0,0 +1,14 @@
+-- Maps a clicked button to a route (tipo). A rule matches on the button payload, the name of the
+-- template the button belongs to, or both; rules with both set take precedence.
+CREATE TABLE IF NOT EXISTS button_routes (
+    id SERIAL PRIMARY KEY,
+    template_name VARCHAR,
+    payload VARCHAR,
+    tipo VARCHAR NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    CHECK (template_name IS NOT NULL OR payload IS NOT NULL)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS button_routes_rule_idx
+    ON button_routes (COALESCE(template_name, ''), COALESCE(payload, ''));
@@ -0,0 +1,6 @@
+ALTER TABLE "button-answers" ADD COLUMN IF NOT EXISTS button_payload VARCHAR;
+ALTER TABLE "button-answers" ADD COLUMN IF NOT EXISTS template_name VARCHAR;
+ALTER TABLE "button-answers" ADD COLUMN IF NOT EXISTS campaign_id VARCHAR;
+ALTER TABLE "button-answers" ADD COLUMN IF NOT EXISTS time_to_click_secs BIGINT;
+
+CREATE INDEX IF NOT EXISTS "button-answers_campaign_id_idx" ON "button-answers" (campaign_id);
@@ -0,0 +1,4 @@
+ALTER TABLE "button-answers-audit" ADD COLUMN IF NOT EXISTS provider VARCHAR;
+ALTER TABLE "button-answers-audit" ADD COLUMN IF NOT EXISTS app_id VARCHAR;
+ALTER TABLE "button-answers-audit" ADD COLUMN IF NOT EXISTS phone_number_id VARCHAR;
+ALTER TABLE "button-answers-audit" ADD COLUMN IF NOT EXISTS contact_name VARCHAR;
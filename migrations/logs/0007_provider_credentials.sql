@@ -0,0 +1,22 @@
+-- Per-source Gupshup credentials. Any NULL column falls back to the global configuration:
+-- api_key to API_KEY_GUP, app_name to parametros.source_name and endpoint to the public Gupshup API.
+-- api_key may be stored encrypted ("enc:v1:..."), see the encrypt-secret command.
+CREATE TABLE IF NOT EXISTS provider_credentials (
+    source VARCHAR PRIMARY KEY,
+    api_key TEXT,
+    app_name VARCHAR,
+    endpoint VARCHAR,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE OR REPLACE FUNCTION notify_source_connections_changed() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('source_connections_changed', TG_TABLE_NAME);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS provider_credentials_notify_source_connections ON provider_credentials;
+CREATE TRIGGER provider_credentials_notify_source_connections
+    AFTER INSERT OR UPDATE OR DELETE ON provider_credentials
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_source_connections_changed();
@@ -0,0 +1,40 @@
+-- Tenants (partner brands) served by the same consumer. A click belongs to the tenant whose
+-- app_id matches the webhook's gs_app_id or whose sources list contains the source number.
+-- NULL credentials fall back to provider_credentials / the global configuration and a NULL
+-- logs_db_url keeps the tenant's logs in this database.
+CREATE TABLE IF NOT EXISTS tenants (
+    id VARCHAR PRIMARY KEY,
+    app_id VARCHAR UNIQUE,
+    sources TEXT[] NOT NULL DEFAULT '{}',
+    logs_db_url TEXT,
+    api_key TEXT,
+    app_name VARCHAR,
+    endpoint VARCHAR,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Reply catalogue per tenant, replacing the built-in BOLSA/FGTS copy. A NULL reply logs
+-- the click without answering (like SEMINTERESSE).
+CREATE TABLE IF NOT EXISTS tenant_replies (
+    tenant_id VARCHAR NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    tipo VARCHAR NOT NULL,
+    reply TEXT,
+    log_message TEXT NOT NULL,
+    PRIMARY KEY (tenant_id, tipo)
+);
+
+-- Route rules can be scoped to a tenant; NULL applies to every tenant.
+ALTER TABLE button_routes ADD COLUMN IF NOT EXISTS tenant_id VARCHAR;
+
+ALTER TABLE "button-answers-audit" ADD COLUMN IF NOT EXISTS tenant_id VARCHAR;
+
+DROP TRIGGER IF EXISTS tenants_notify_source_connections ON tenants;
+CREATE TRIGGER tenants_notify_source_connections
+    AFTER INSERT OR UPDATE OR DELETE ON tenants
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_source_connections_changed();
+
+DROP TRIGGER IF EXISTS tenant_replies_notify_source_connections ON tenant_replies;
+CREATE TRIGGER tenant_replies_notify_source_connections
+    AFTER INSERT OR UPDATE OR DELETE ON tenant_replies
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_source_connections_changed();
@@ -0,0 +1,8 @@
+-- Numbers that asked to stop receiving messages. Checked before every outbound send.
+CREATE TABLE IF NOT EXISTS opt_outs (
+    num VARCHAR PRIMARY KEY,
+    source VARCHAR,
+    tenant_id VARCHAR,
+    keyword VARCHAR NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
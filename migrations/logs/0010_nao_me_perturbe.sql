@@ -0,0 +1,15 @@
+-- Imports of the Não Me Perturbe registry export. Each import is a version; the latest
+-- completed one is enforced and older versions are kept for reference.
+CREATE TABLE IF NOT EXISTS nmp_imports (
+    id SERIAL PRIMARY KEY,
+    file_name VARCHAR NOT NULL,
+    row_count INTEGER NOT NULL DEFAULT 0,
+    completed BOOLEAN NOT NULL DEFAULT FALSE,
+    imported_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS nmp_numbers (
+    import_id INTEGER NOT NULL REFERENCES nmp_imports(id) ON DELETE CASCADE,
+    num VARCHAR NOT NULL,
+    PRIMARY KEY (import_id, num)
+);
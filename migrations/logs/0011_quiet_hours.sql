@@ -0,0 +1,35 @@
+-- Hours (America/Sao_Paulo) in which replies may be sent, per source. Sources without a row
+-- use DEFAULT_SEND_WINDOW, or are never restricted when it is not set. days holds ISO
+-- weekdays (1 = Monday ... 7 = Sunday).
+CREATE TABLE IF NOT EXISTS send_windows (
+    source VARCHAR PRIMARY KEY,
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL,
+    days SMALLINT[] NOT NULL DEFAULT '{1,2,3,4,5,6,7}'
+);
+
+-- Days on which no reply is sent by any restricted source.
+CREATE TABLE IF NOT EXISTS holidays (
+    day DATE PRIMARY KEY,
+    description VARCHAR
+);
+
+-- Replies held back until the source's window opens, delivered by the scheduler.
+CREATE TABLE IF NOT EXISTS scheduled_sends (
+    id BIGSERIAL PRIMARY KEY,
+    num VARCHAR NOT NULL,
+    source VARCHAR NOT NULL,
+    tenant_id VARCHAR,
+    tipo VARCHAR NOT NULL,
+    reply TEXT NOT NULL,
+    send_after TIMESTAMPTZ NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    claimed_at TIMESTAMPTZ,
+    sent_at TIMESTAMPTZ,
+    provider_response TEXT,
+    last_error TEXT,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS scheduled_sends_due ON scheduled_sends (status, send_after);
@@ -0,0 +1,19 @@
+-- Reminders for customers who did not answer a question (BOLSA/FGTS). At most one active
+-- follow-up per number; any inbound message from the number cancels it.
+CREATE TABLE IF NOT EXISTS follow_ups (
+    id BIGSERIAL PRIMARY KEY,
+    num VARCHAR NOT NULL,
+    source VARCHAR NOT NULL,
+    tenant_id VARCHAR,
+    tipo VARCHAR NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'active',
+    reminders_sent INTEGER NOT NULL DEFAULT 0,
+    next_at TIMESTAMPTZ NOT NULL,
+    last_reminder_at TIMESTAMPTZ,
+    last_error TEXT,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    closed_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS follow_ups_active_num ON follow_ups (num) WHERE status = 'active';
+CREATE INDEX IF NOT EXISTS follow_ups_due ON follow_ups (status, next_at);
@@ -0,0 +1,23 @@
+-- Last inbound message per customer, used to tell whether the WhatsApp 24h customer
+-- service window is still open.
+CREATE TABLE IF NOT EXISTS customer_activity (
+    num VARCHAR PRIMARY KEY,
+    source VARCHAR,
+    last_inbound_at TIMESTAMPTZ NOT NULL
+);
+
+-- Approved template sent instead of a route's free-form reply outside the 24h window.
+-- params are rendered like replies ({{primeiro_nome}}, ...). tenant_id NULL applies to every tenant.
+CREATE TABLE IF NOT EXISTS route_templates (
+    id SERIAL PRIMARY KEY,
+    tenant_id VARCHAR,
+    tipo VARCHAR NOT NULL,
+    template_id VARCHAR NOT NULL,
+    params TEXT[] NOT NULL DEFAULT '{}'
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS route_templates_tenant_tipo ON route_templates (COALESCE(tenant_id, ''), tipo);
+
+ALTER TABLE scheduled_sends ALTER COLUMN reply DROP NOT NULL;
+ALTER TABLE scheduled_sends ADD COLUMN IF NOT EXISTS template_id VARCHAR;
+ALTER TABLE scheduled_sends ADD COLUMN IF NOT EXISTS template_params TEXT[];
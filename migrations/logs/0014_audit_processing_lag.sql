@@ -0,0 +1,2 @@
+-- Seconds between the customer's message and its processing.
+ALTER TABLE "button-answers-audit" ADD COLUMN IF NOT EXISTS processing_lag_secs BIGINT;
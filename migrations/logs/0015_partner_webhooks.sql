@@ -0,0 +1,31 @@
+-- Partner endpoints notified of processed clicks from a source. secret signs each body
+-- (HMAC-SHA256, X-Signature header) and may be stored encrypted ("enc:v1:...").
+CREATE TABLE IF NOT EXISTS partner_webhooks (
+    id SERIAL PRIMARY KEY,
+    partner VARCHAR NOT NULL,
+    source VARCHAR NOT NULL,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS partner_webhooks_source ON partner_webhooks (source) WHERE active;
+
+-- One row per event and endpoint: the retry queue and the delivery history.
+CREATE TABLE IF NOT EXISTS partner_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id INTEGER NOT NULL REFERENCES partner_webhooks(id) ON DELETE CASCADE,
+    event VARCHAR NOT NULL,
+    payload TEXT NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_status_code INTEGER,
+    last_error TEXT,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS partner_deliveries_due ON partner_deliveries (status, next_attempt_at);
+CREATE INDEX IF NOT EXISTS partner_deliveries_webhook ON partner_deliveries (webhook_id, created_at DESC);
@@ -0,0 +1,26 @@
+-- Replies written together with their "button-answers" row before anything is sent, then
+-- delivered by the consumer (and retried by the scheduler). provider_message_id is Gupshup's
+-- messageId. Rows found stuck in 'sending' become 'unconfirmed': the provider may or may not
+-- have accepted them.
+CREATE TABLE IF NOT EXISTS outbox (
+    id BIGSERIAL PRIMARY KEY,
+    log_id INTEGER REFERENCES "button-answers"(id) ON DELETE SET NULL,
+    num VARCHAR NOT NULL,
+    source VARCHAR NOT NULL,
+    tenant_id VARCHAR,
+    tipo VARCHAR NOT NULL,
+    reply TEXT,
+    template_id VARCHAR,
+    template_params TEXT[],
+    status VARCHAR NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    claimed_at TIMESTAMPTZ,
+    provider_message_id VARCHAR,
+    provider_response TEXT,
+    last_error TEXT,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    sent_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS outbox_due ON outbox (status, next_attempt_at);
@@ -0,0 +1,5 @@
+-- Set on rows written back from the local spool, so an interrupted drain can be repeated
+-- without duplicating them.
+ALTER TABLE "button-answers" ADD COLUMN IF NOT EXISTS spool_id VARCHAR;
+
+CREATE UNIQUE INDEX IF NOT EXISTS button_answers_spool_id ON "button-answers" (spool_id) WHERE spool_id IS NOT NULL;
@@ -0,0 +1,6 @@
+-- A rule is unique per tenant: the same payload/template may be routed differently for each
+-- tenant and once more as a shared (NULL tenant) rule.
+DROP INDEX IF EXISTS button_routes_rule_idx;
+
+CREATE UNIQUE INDEX button_routes_rule_idx
+    ON button_routes (COALESCE(tenant_id, ''), COALESCE(template_name, ''), COALESCE(payload, ''));
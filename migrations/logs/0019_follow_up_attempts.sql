@@ -0,0 +1,2 @@
+-- Consecutive failed attempts at the current reminder; reset once it is sent.
+ALTER TABLE follow_ups ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0;
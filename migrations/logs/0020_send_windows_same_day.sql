@@ -0,0 +1,4 @@
+-- Windows are same-day ranges. NOT VALID keeps existing rows loading; the consumer ignores
+-- any that break the rule.
+ALTER TABLE send_windows DROP CONSTRAINT IF EXISTS send_windows_same_day;
+ALTER TABLE send_windows ADD CONSTRAINT send_windows_same_day CHECK (start_time < end_time) NOT VALID;
@@ -0,0 +1,19 @@
+-- Optional: lets the consumer drop its source connection cache as soon as `conexoes` or
+-- `parametros` change, instead of waiting for SOURCE_CACHE_TTL_SECS. These tables belong to
+-- the main database, so this is not applied by the `migrate` command; run it by hand there.
+CREATE OR REPLACE FUNCTION notify_source_connections_changed() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('source_connections_changed', TG_TABLE_NAME);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS conexoes_notify_source_connections ON conexoes;
+CREATE TRIGGER conexoes_notify_source_connections
+    AFTER INSERT OR UPDATE OR DELETE ON conexoes
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_source_connections_changed();
+
+DROP TRIGGER IF EXISTS parametros_notify_source_connections ON parametros;
+CREATE TRIGGER parametros_notify_source_connections
+    AFTER INSERT OR UPDATE OR DELETE ON parametros
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_source_connections_changed();
@@ -0,0 +1,10 @@
+-- Optional: the outbound template messages the campaign system records, used for campaign
+-- attribution and template-aware routing. The table belongs to the main database, so this is
+-- not applied by the `migrate` command; the campaign system (or an operator) creates it there.
+-- Without it clicks are still handled, routed on payload rules and button text only.
+CREATE TABLE IF NOT EXISTS template_messages (
+    message_id VARCHAR PRIMARY KEY,
+    template_name VARCHAR NOT NULL,
+    campaign_id VARCHAR,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -3,22 +3,97 @@ use std::collections::HashMap;
 use reqwest::{self, Client};
 use log::{info, error};
 
-pub async fn send_gupshup_message(apikey: &str, body: &str, conn: (String, String), to: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let client = Client::new();
+pub const GUPSHUP_MSG_ENDPOINT: &str = "https://api.gupshup.io/wa/api/v1/msg";
+pub const GUPSHUP_TEMPLATE_ENDPOINT: &str = "https://api.gupshup.io/wa/api/v1/template/msg";
+
+/// The Gupshup app a source number replies through.
+#[derive(Debug, Clone)]
+pub struct ProviderSettings {
+    pub api_key: String,
+    pub app_name: String,
+    pub endpoint: String,
+}
+
+impl ProviderSettings {
+    /// Template messages go to `.../template/msg` next to the session message endpoint.
+    pub fn template_endpoint(&self) -> String {
+        match self.endpoint.strip_suffix("/msg") {
+            Some(base) => format!("{}/template/msg", base),
+            None => GUPSHUP_TEMPLATE_ENDPOINT.to_string(),
+        }
+    }
+}
+
+/// What is sent to the customer: a free-form session message, or an approved template
+/// (required outside the 24h customer service window).
+#[derive(Debug, Clone)]
+pub enum OutboundMessage {
+    Text(String),
+    Template { id: String, params: Vec<String> },
+}
+
+impl OutboundMessage {
+    /// A readable form for dry-run output, shadow records and logs.
+    pub fn describe(&self) -> String {
+        match self {
+            OutboundMessage::Text(body) => body.clone(),
+            OutboundMessage::Template { id, params } => format!("[template {}] {}", id, params.join(" | ")),
+        }
+    }
+}
+
+/// Gupshup answers `{"status": "submitted", "messageId": "..."}`.
+pub fn provider_message_id(response: &str) -> Option<String> {
+    let response: serde_json::Value = serde_json::from_str(response).ok()?;
+    response.get("messageId")?.as_str().map(str::to_string)
+}
+
+pub async fn send_outbound(settings: &ProviderSettings, message: &OutboundMessage, source: &str, to: &str) -> Result<String, Box<dyn std::error::Error>> {
+    match message {
+        OutboundMessage::Text(body) => send_gupshup_message(settings, body, source, to).await,
+        OutboundMessage::Template { id, params } => send_gupshup_template(settings, id, params, source, to).await,
+    }
+}
+
+pub async fn send_gupshup_message(settings: &ProviderSettings, body: &str, source: &str, to: &str) -> Result<String, Box<dyn std::error::Error>> {
     let mut form: HashMap<&str, &str> = HashMap::new();
     form.insert("channel", "whatsapp");
-    form.insert("source", &conn.1);
+    form.insert("source", source);
     form.insert("destination", to);
-    form.insert("message", &body);
-    form.insert("src.name", &conn.0);
+    form.insert("message", body);
+    form.insert("src.name", &settings.app_name);
 
-    info!("Sending Gupshup message to {} via source {}", to, &conn.1);
-    let response = match client.post("https://api.gupshup.io/wa/api/v1/msg")
+    info!("Sending Gupshup message to {} via source {} (app {})", to, source, settings.app_name);
+    post_form(settings, &settings.endpoint, &form).await
+}
+
+pub async fn send_gupshup_template(
+    settings: &ProviderSettings,
+    template_id: &str,
+    params: &[String],
+    source: &str,
+    to: &str
+) -> Result<String, Box<dyn std::error::Error>> {
+    let template = serde_json::json!({ "id": template_id, "params": params }).to_string();
+    let mut form: HashMap<&str, &str> = HashMap::new();
+    form.insert("channel", "whatsapp");
+    form.insert("source", source);
+    form.insert("destination", to);
+    form.insert("template", &template);
+    form.insert("src.name", &settings.app_name);
+
+    info!("Sending Gupshup template {} to {} via source {} (app {})", template_id, to, source, settings.app_name);
+    post_form(settings, &settings.template_endpoint(), &form).await
+}
+
+async fn post_form(settings: &ProviderSettings, endpoint: &str, form: &HashMap<&str, &str>) -> Result<String, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let response = match client.post(endpoint)
         .header("Content-Type", "application/x-www-form-urlencoded")
-        .header("apikey", apikey)
+        .header("apikey", &settings.api_key)
         .header("cache-control", "no-cache")
         .header("Cache-Control", "no-cache")
-        .form(&form)
+        .form(form)
         .send()
         .await {
             Ok(resp) => resp,
@@ -37,5 +112,5 @@ pub async fn send_gupshup_message(apikey: &str, body: &str, conn: (String, Strin
     }
 
     info!("Gupshup message sent successfully. Status: {}. Body: {}", status, resp_text);
-    Ok(())
+    Ok(resp_text)
 }
@@ -0,0 +1,17 @@
+use crate::config::config::EnvVars;
+use crate::crypto::secret;
+
+const USAGE: &str = "usage: consume-button-templates encrypt-secret <value>";
+
+/// Prints `value` encrypted with `CREDENTIALS_KEY`, ready to store in `provider_credentials.api_key`.
+pub fn run(env_vars: &EnvVars, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(value) = args.first() else {
+        return Err(USAGE.into());
+    };
+    let Some(key) = &env_vars.credentials_key else {
+        return Err("CREDENTIALS_KEY is not configured".into());
+    };
+
+    println!("{}", secret::encrypt(key, value)?);
+    Ok(())
+}
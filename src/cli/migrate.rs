@@ -0,0 +1,19 @@
+use crate::db::migrate;
+use crate::process::state::AppState;
+use log::info;
+
+pub async fn run(state: &AppState) -> Result<(), Box<dyn std::error::Error>> {
+    let mut db_logs = state.pools.client(&state.env_vars.db_url_logs).await?;
+
+    let applied = migrate::run_migrations(&mut db_logs, migrate::LOGS_MIGRATIONS).await?;
+    info!("Applied {} migration(s) to the logs database", applied);
+    println!("Logs database is up to date ({} migration(s) applied)", applied);
+
+    for (tenant, url) in state.tenants.logs_db_urls(&db_logs).await? {
+        let mut tenant_logs = state.pools.client(&url).await?;
+        let applied = migrate::run_migrations(&mut tenant_logs, migrate::LOGS_MIGRATIONS).await?;
+        info!("Applied {} migration(s) to the logs database of tenant {}", applied, tenant);
+        println!("Logs database of tenant {} is up to date ({} migration(s) applied)", tenant, applied);
+    }
+    Ok(())
+}
@@ -0,0 +1,5 @@
+pub mod credentials;
+pub mod migrate;
+pub mod nmp;
+pub mod partners;
+pub mod replay;
\ No newline at end of file
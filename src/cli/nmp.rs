@@ -0,0 +1,97 @@
+use crate::process::nmp::normalize_number;
+use crate::process::state::AppState;
+use log::{info, warn};
+use std::fs;
+
+const USAGE: &str = "usage: consume-button-templates import-nmp <file.csv> [--column <n>]";
+
+/// Numbers inserted per statement.
+const BATCH_SIZE: usize = 5000;
+
+/// Imported versions whose numbers are kept; older imports keep only their `nmp_imports` row.
+const KEEP_VERSIONS: i64 = 3;
+
+fn parse_args(args: &[String]) -> Result<(String, usize), Box<dyn std::error::Error>> {
+    let mut file = None;
+    let mut column = 0;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--column" => match iter.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n > 0 => column = n - 1,
+                _ => return Err(format!("--column requires a 1-based column number\n{}", USAGE).into()),
+            },
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}\n{}", flag, USAGE).into()),
+            path => file = Some(path.to_string()),
+        }
+    }
+
+    match file {
+        Some(file) => Ok((file, column)),
+        None => Err(USAGE.into()),
+    }
+}
+
+/// Reads the number column of a `,` or `;` separated export. Header and malformed lines are skipped.
+fn read_numbers(file: &str, column: usize) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(file)?;
+    let mut skipped = 0;
+    let mut numbers: Vec<String> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let field = line.split([',', ';']).nth(column).unwrap_or_default();
+            let number = normalize_number(field.trim().trim_matches('"'));
+            if number.is_none() {
+                skipped += 1;
+            }
+            number
+        })
+        .collect();
+    if skipped > 0 {
+        warn!("Skipped {} line(s) without a valid number in {}", skipped, file);
+    }
+    numbers.sort();
+    numbers.dedup();
+    Ok(numbers)
+}
+
+/// Imports a Não Me Perturbe export as a new version. The version only becomes active once
+/// every number is in, so a failed import leaves the previous list enforced.
+pub async fn run(state: &AppState, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (file, column) = parse_args(args)?;
+    let numbers = read_numbers(&file, column)?;
+    info!("Importing {} number(s) from {}", numbers.len(), file);
+
+    let mut db_logs = state.pools.client(&state.env_vars.db_url_logs).await?;
+    let transaction = db_logs.transaction().await?;
+    // Pool connections cap statements at 30s; deleting an old version of a multi-million row
+    // registry takes longer, and a timeout would roll the whole import back.
+    transaction.execute("SET LOCAL statement_timeout = 0", &[]).await?;
+
+    let import_id: i32 = transaction
+        .query_one("INSERT INTO nmp_imports (file_name) VALUES ($1) RETURNING id", &[&file])
+        .await?
+        .get("id");
+
+    for batch in numbers.chunks(BATCH_SIZE) {
+        transaction.execute(
+            "INSERT INTO nmp_numbers (import_id, num) SELECT $1, UNNEST($2::VARCHAR[])",
+            &[&import_id, &batch]
+        ).await?;
+    }
+
+    transaction.execute(
+        "UPDATE nmp_imports SET completed = TRUE, row_count = $2 WHERE id = $1",
+        &[&import_id, &(numbers.len() as i32)]
+    ).await?;
+    transaction.execute(
+        "DELETE FROM nmp_numbers WHERE import_id NOT IN (SELECT id FROM nmp_imports WHERE completed ORDER BY id DESC LIMIT $1)",
+        &[&KEEP_VERSIONS]
+    ).await?;
+    transaction.commit().await?;
+
+    println!("Imported Não Me Perturbe version {} ({} number(s))", import_id, numbers.len());
+    Ok(())
+}
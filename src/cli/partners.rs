@@ -0,0 +1,52 @@
+use crate::db::partners::fetch_delivery_history;
+use crate::process::state::AppState;
+
+const USAGE: &str = "usage: consume-button-templates partner-deliveries <partner> [--limit <n>]";
+
+fn parse_args(args: &[String]) -> Result<(String, i64), Box<dyn std::error::Error>> {
+    let mut partner = None;
+    let mut limit = 50;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--limit" => match iter.next().and_then(|n| n.parse::<i64>().ok()) {
+                Some(n) if n > 0 => limit = n,
+                _ => return Err(format!("--limit requires a positive number\n{}", USAGE).into()),
+            },
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}\n{}", flag, USAGE).into()),
+            name => partner = Some(name.to_string()),
+        }
+    }
+
+    match partner {
+        Some(partner) => Ok((partner, limit)),
+        None => Err(USAGE.into()),
+    }
+}
+
+/// Prints the latest webhook deliveries to a partner, newest first.
+pub async fn run(state: &AppState, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (partner, limit) = parse_args(args)?;
+    let db_logs = state.pools.client(&state.env_vars.db_url_logs).await?;
+    let history = fetch_delivery_history(&db_logs, &partner, limit).await?;
+
+    if history.is_empty() {
+        println!("No deliveries to {}", partner);
+        return Ok(());
+    }
+    for delivery in history {
+        println!(
+            "#{} {} {} {} attempts={} http={} delivered_at={} error={}",
+            delivery.id,
+            delivery.created_at.to_rfc3339(),
+            delivery.event,
+            delivery.status,
+            delivery.attempts,
+            delivery.last_status_code.map(|code| code.to_string()).unwrap_or_else(|| "-".to_string()),
+            delivery.delivered_at.map(|at| at.to_rfc3339()).unwrap_or_else(|| "-".to_string()),
+            delivery.last_error.as_deref().unwrap_or("-"),
+        );
+    }
+    Ok(())
+}
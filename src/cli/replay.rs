@@ -0,0 +1,163 @@
+use crate::process::state::AppState;
+use crate::process::process::{self, Mode};
+use crate::rabbit::connect as rmq_connect;
+use lapin::message::Delivery;
+use lapin::options::{BasicAckOptions, BasicGetOptions, BasicNackOptions};
+use log::{info, error, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+const USAGE: &str = "usage: consume-button-templates replay [--dry-run] (<file-or-directory> | --dlq <queue>)";
+
+enum ReplaySource {
+    Path(PathBuf),
+    Dlq(String),
+}
+
+struct ReplayArgs {
+    source: ReplaySource,
+    dry_run: bool,
+}
+
+#[derive(Default)]
+struct ReplayStats {
+    processed: usize,
+    failed: usize,
+}
+
+fn parse_args(args: &[String]) -> Result<ReplayArgs, Box<dyn std::error::Error>> {
+    let mut dry_run = false;
+    let mut source = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--dlq" => match iter.next() {
+                Some(queue) => source = Some(ReplaySource::Dlq(queue.clone())),
+                None => return Err(format!("--dlq requires a queue name\n{}", USAGE).into()),
+            },
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}\n{}", flag, USAGE).into()),
+            path => source = Some(ReplaySource::Path(PathBuf::from(path))),
+        }
+    }
+
+    match source {
+        Some(source) => Ok(ReplayArgs { source, dry_run }),
+        None => Err(USAGE.into()),
+    }
+}
+
+pub async fn run(state: &AppState, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let args = parse_args(args)?;
+    let mode = if args.dry_run { Mode::DryRun } else { Mode::Live };
+    info!("Starting webhook replay (mode: {:?})", mode);
+
+    let stats = match args.source {
+        ReplaySource::Path(path) => replay_path(&path, state, &mode).await?,
+        ReplaySource::Dlq(queue) => replay_dlq(&queue, state, &mode).await?,
+    };
+
+    state.log_writer.flush().await;
+    println!("Replay finished: {} processed, {} failed", stats.processed, stats.failed);
+    if stats.failed > 0 {
+        return Err(format!("{} webhook(s) failed to replay", stats.failed).into());
+    }
+    Ok(())
+}
+
+async fn replay_one(
+    data: &[u8],
+    state: &AppState,
+    mode: &Mode,
+    stats: &mut ReplayStats
+) -> bool {
+    match process::process_webhook(data, state, mode).await {
+        Ok(_) => {
+            stats.processed += 1;
+            true
+        }
+        Err(e) => {
+            error!("Failed to replay webhook: {}", e);
+            stats.failed += 1;
+            false
+        }
+    }
+}
+
+async fn replay_path(
+    path: &Path,
+    state: &AppState,
+    mode: &Mode
+) -> Result<ReplayStats, Box<dyn std::error::Error>> {
+    let files = if path.is_dir() {
+        let mut files: Vec<PathBuf> = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.is_file())
+            .collect();
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut stats = ReplayStats::default();
+    for file in files {
+        info!("Replaying webhooks from {}", file.display());
+        for payload in read_payloads(&file)? {
+            replay_one(payload.as_bytes(), state, mode, &mut stats).await;
+        }
+    }
+    Ok(stats)
+}
+
+/// A file holds either a single JSON document or one webhook per line (JSONL).
+fn read_payloads(file: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(file)?;
+    if serde_json::from_str::<serde_json::Value>(&content).is_ok() {
+        return Ok(vec![content]);
+    }
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+async fn replay_dlq(
+    queue: &str,
+    state: &AppState,
+    mode: &Mode
+) -> Result<ReplayStats, Box<dyn std::error::Error>> {
+    let (errors_tx, _errors) = mpsc::unbounded_channel();
+    let connection = rmq_connect::connect_rabbitmq(&state.env_vars.rabbit_url, errors_tx).await?;
+    let channel = connection.create_channel().await?;
+
+    // Deliveries that are not acked stay unacknowledged on this channel until the end,
+    // so basic_get never hands them back and the loop terminates once the queue is drained.
+    let mut kept: Vec<Delivery> = Vec::new();
+    let mut stats = ReplayStats::default();
+
+    while let Some(message) = channel.basic_get(queue, BasicGetOptions::default()).await? {
+        let delivery = message.delivery;
+        let replayed = replay_one(&delivery.data, state, mode, &mut stats).await;
+
+        if replayed && matches!(mode, Mode::Live) {
+            delivery.ack(BasicAckOptions::default()).await?;
+        } else {
+            kept.push(delivery);
+        }
+    }
+
+    if !kept.is_empty() {
+        warn!("Returning {} message(s) to {}", kept.len(), queue);
+    }
+    for delivery in kept {
+        delivery.nack(BasicNackOptions { requeue: true, ..BasicNackOptions::default() }).await?;
+    }
+
+    connection.close(200, "Replay finished").await?;
+    Ok(stats)
+}
@@ -1,13 +1,50 @@
 use dotenv::dotenv;
+use chrono::NaiveTime;
 use std::env;
 
 pub struct EnvVars {
     pub db_url: String,
     pub rabbit_url: String,
+    #[allow(dead_code)]
     pub api_key_huggy: String,
     pub api_key_gup: String,
+    #[allow(dead_code)]
     pub api_key_huggy2: String,
-    pub db_url_logs: String
+    pub db_url_logs: String,
+    pub rabbit_queues: Vec<String>,
+    pub rabbit_backoff_initial_secs: u64,
+    pub rabbit_backoff_max_secs: u64,
+    pub shadow_mode: bool,
+    pub shadow_queue: String,
+    pub shadow_output: Option<String>,
+    pub audit_compress_payload: bool,
+    pub http_listen_addr: Option<String>,
+    pub http_publish_queue: String,
+    pub meta_verify_token: Option<String>,
+    pub meta_app_secret: Option<String>,
+    pub gupshup_webhook_token: Option<String>,
+    pub amqp_signing_secret: Option<String>,
+    pub quarantine_queue: String,
+    pub source_cache_ttl_secs: u64,
+    pub source_cache_negative_ttl_secs: u64,
+    pub credentials_key: Option<String>,
+    pub multi_tenant: bool,
+    pub optout_keywords: Vec<String>,
+    pub nmp_blocked_tipos: Vec<String>,
+    pub default_send_window: Option<(NaiveTime, NaiveTime)>,
+    pub scheduler_interval_secs: u64,
+    pub follow_up_tipos: Vec<String>,
+    pub follow_up_delay_mins: i64,
+    pub follow_up_max_reminders: i32,
+    pub click_max_age_secs: Option<i64>,
+    pub events_exchange: Option<String>,
+    pub events_routing_key: String,
+    pub outbox_resend_unconfirmed: bool,
+    pub log_spool_path: String,
+    pub log_spool_max_bytes: u64,
+    pub log_batch_size: usize,
+    pub log_flush_interval_ms: u64,
+    pub log_writer_capacity: usize
 }
 
 pub fn load() -> EnvVars {
@@ -20,6 +57,43 @@ pub fn load() -> EnvVars {
     let api_key_gup = env::var("API_KEY_GUP").expect("NO API_KEY_GUP FOUND IN THE .ENV FILE!");
     let api_key_huggy2 = env::var("API_KEY_HUGGY2").expect("NO API_KEY_HUGGY2 FOUND IN THE .ENV FILE!");
     let db_url_logs = env::var("DB_URL_LOGS").expect("NO DB_URL_LOGS FOUND IN THE .ENV FILE!");
+    let rabbit_queues = list_var("RABBIT_QUEUES", "button_templates");
+    if rabbit_queues.is_empty() {
+        panic!("RABBIT_QUEUES MUST LIST AT LEAST ONE QUEUE!");
+    }
+    let rabbit_backoff_initial_secs = parse_var("RABBIT_BACKOFF_INITIAL_SECS", 1);
+    let rabbit_backoff_max_secs = parse_var("RABBIT_BACKOFF_MAX_SECS", 60);
+    let shadow_mode = parse_var("SHADOW_MODE", false);
+    let shadow_queue = env::var("SHADOW_QUEUE").unwrap_or_else(|_| "button_templates_shadow".to_string());
+    let shadow_output = optional_var("SHADOW_OUTPUT");
+    let audit_compress_payload = parse_var("AUDIT_COMPRESS_PAYLOAD", false);
+    let http_listen_addr = optional_var("HTTP_LISTEN_ADDR");
+    let http_publish_queue = env::var("HTTP_PUBLISH_QUEUE").unwrap_or_else(|_| rabbit_queues.first().cloned().unwrap_or_else(|| "button_templates".to_string()));
+    let meta_verify_token = optional_var("META_VERIFY_TOKEN");
+    let meta_app_secret = optional_var("META_APP_SECRET");
+    let gupshup_webhook_token = optional_var("GUPSHUP_WEBHOOK_TOKEN");
+    let amqp_signing_secret = optional_var("AMQP_SIGNING_SECRET");
+    let quarantine_queue = env::var("QUARANTINE_QUEUE").unwrap_or_else(|_| "button_templates_quarantine".to_string());
+    let source_cache_ttl_secs = parse_var("SOURCE_CACHE_TTL_SECS", 300);
+    let source_cache_negative_ttl_secs = parse_var("SOURCE_CACHE_NEGATIVE_TTL_SECS", 60);
+    let credentials_key = optional_var("CREDENTIALS_KEY");
+    let multi_tenant = parse_var("MULTI_TENANT", false);
+    let optout_keywords = list_var("OPTOUT_KEYWORDS", "parar,sair,não quero");
+    let nmp_blocked_tipos = list_var("NMP_BLOCKED_TIPOS", "BOLSA,FGTS");
+    let default_send_window = optional_var("DEFAULT_SEND_WINDOW").map(|value| window_var("DEFAULT_SEND_WINDOW", &value));
+    let scheduler_interval_secs = parse_var("SCHEDULER_INTERVAL_SECS", 30);
+    let follow_up_tipos = list_var("FOLLOW_UP_TIPOS", "BOLSA,FGTS");
+    let follow_up_delay_mins = parse_var("FOLLOW_UP_DELAY_MINS", 60);
+    let follow_up_max_reminders = parse_var("FOLLOW_UP_MAX_REMINDERS", 0);
+    let click_max_age_secs = optional_var("CLICK_MAX_AGE_SECS").map(|_| parse_var("CLICK_MAX_AGE_SECS", 0));
+    let events_exchange = optional_var("EVENTS_EXCHANGE");
+    let events_routing_key = env::var("EVENTS_ROUTING_KEY").unwrap_or_else(|_| "button_click.processed".to_string());
+    let outbox_resend_unconfirmed = parse_var("OUTBOX_RESEND_UNCONFIRMED", false);
+    let log_spool_path = env::var("LOG_SPOOL_PATH").unwrap_or_else(|_| "button-answers.spool.jsonl".to_string());
+    let log_spool_max_bytes = parse_var("LOG_SPOOL_MAX_BYTES", 100 * 1024 * 1024);
+    let log_batch_size = parse_var("LOG_BATCH_SIZE", 500);
+    let log_flush_interval_ms = parse_var("LOG_FLUSH_INTERVAL_MS", 1000);
+    let log_writer_capacity = parse_var("LOG_WRITER_CAPACITY", 10000);
 
     EnvVars {
         db_url,
@@ -27,6 +101,94 @@ pub fn load() -> EnvVars {
         api_key_huggy,
         api_key_gup,
         api_key_huggy2,
-        db_url_logs
+        db_url_logs,
+        rabbit_queues,
+        rabbit_backoff_initial_secs,
+        rabbit_backoff_max_secs,
+        shadow_mode,
+        shadow_queue,
+        shadow_output,
+        audit_compress_payload,
+        http_listen_addr,
+        http_publish_queue,
+        meta_verify_token,
+        meta_app_secret,
+        gupshup_webhook_token,
+        amqp_signing_secret,
+        quarantine_queue,
+        source_cache_ttl_secs,
+        source_cache_negative_ttl_secs,
+        credentials_key,
+        multi_tenant,
+        optout_keywords,
+        nmp_blocked_tipos,
+        default_send_window,
+        scheduler_interval_secs,
+        follow_up_tipos,
+        follow_up_delay_mins,
+        follow_up_max_reminders,
+        click_max_age_secs,
+        events_exchange,
+        events_routing_key,
+        outbox_resend_unconfirmed,
+        log_spool_path,
+        log_spool_max_bytes,
+        log_batch_size,
+        log_flush_interval_ms,
+        log_writer_capacity
+    }
+}
+
+fn parse_var<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| panic!("INVALID VALUE FOR {} IN THE .ENV FILE!", name)),
+        Err(_) => default,
+    }
+}
+
+fn optional_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn list_var(name: &str, default: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_else(|_| default.to_string())
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Parses an `HH:MM-HH:MM` window. Windows are same-day ranges, so the start must come
+/// before the end; overnight windows such as `21:00-08:00` are refused.
+fn window_var(name: &str, value: &str) -> (NaiveTime, NaiveTime) {
+    let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").ok();
+    match value.split_once('-').map(|(start, end)| (parse(start), parse(end))) {
+        Some((Some(start), Some(end))) if start < end => (start, end),
+        _ => panic!("INVALID VALUE FOR {} IN THE .ENV FILE!", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_same_day_window() {
+        let (start, end) = window_var("DEFAULT_SEND_WINDOW", "08:00-20:00");
+        assert_eq!(start, NaiveTime::from_hms_opt(8, 0, 0).unwrap());
+        assert_eq!(end, NaiveTime::from_hms_opt(20, 0, 0).unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    #[should_panic(expected = "DEFAULT_SEND_WINDOW")]
+    fn rejects_an_overnight_window() {
+        window_var("DEFAULT_SEND_WINDOW", "21:00-08:00");
+    }
+
+    #[test]
+    #[should_panic(expected = "DEFAULT_SEND_WINDOW")]
+    fn rejects_an_empty_window() {
+        window_var("DEFAULT_SEND_WINDOW", "09:00-09:00");
+    }
+}
@@ -0,0 +1 @@
+pub mod secret;
\ No newline at end of file
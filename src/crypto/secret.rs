@@ -0,0 +1,102 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// Prefix marking a value stored as base64(nonce || AES-256-GCM ciphertext).
+pub const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+const NONCE_LEN: usize = 12;
+
+fn cipher(key_b64: &str) -> Result<Aes256Gcm, Box<dyn std::error::Error>> {
+    let key = STANDARD.decode(key_b64.trim())?;
+    if key.len() != 32 {
+        return Err("CREDENTIALS_KEY must be 32 bytes, base64 encoded".into());
+    }
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+}
+
+pub fn encrypt(key_b64: &str, plaintext: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let cipher = cipher(key_b64)?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| "Failed to encrypt secret")?;
+
+    let mut stored = nonce.to_vec();
+    stored.extend_from_slice(&ciphertext);
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(stored)))
+}
+
+/// Returns stored values without the `enc:v1:` prefix unchanged, so plaintext rows keep working.
+pub fn decrypt(key_b64: Option<&str>, stored: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let Some(encoded) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+        return Ok(stored.to_string());
+    };
+    let Some(key_b64) = key_b64 else {
+        return Err("Encrypted secret found but CREDENTIALS_KEY is not configured".into());
+    };
+
+    let bytes = STANDARD.decode(encoded)?;
+    if bytes.len() <= NONCE_LEN {
+        return Err("Encrypted secret is too short".into());
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let plaintext = cipher(key_b64)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Failed to decrypt secret, check CREDENTIALS_KEY")?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+    const OTHER_KEY: &str = "HxwdHhscGRoXGBUWExQREg8QDQ4LDAkKBwgFBgMEAQI=";
+
+    #[test]
+    fn round_trip() {
+        let stored = encrypt(KEY, "gupshup-api-key").unwrap();
+        assert!(stored.starts_with(ENCRYPTED_PREFIX));
+        assert!(!stored.contains("gupshup-api-key"));
+        assert_eq!(decrypt(Some(KEY), &stored).unwrap(), "gupshup-api-key");
+    }
+
+    #[test]
+    fn each_encryption_uses_a_fresh_nonce() {
+        assert_ne!(encrypt(KEY, "secret").unwrap(), encrypt(KEY, "secret").unwrap());
+    }
+
+    #[test]
+    fn wrong_key_fails() {
+        let stored = encrypt(KEY, "gupshup-api-key").unwrap();
+        assert!(decrypt(Some(OTHER_KEY), &stored).is_err());
+    }
+
+    #[test]
+    fn missing_key_fails_for_encrypted_values() {
+        let stored = encrypt(KEY, "gupshup-api-key").unwrap();
+        assert!(decrypt(None, &stored).is_err());
+    }
+
+    #[test]
+    fn tampered_ciphertext_fails() {
+        let stored = encrypt(KEY, "gupshup-api-key").unwrap();
+        let mut bytes = STANDARD.decode(stored.strip_prefix(ENCRYPTED_PREFIX).unwrap()).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        let tampered = format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(bytes));
+        assert!(decrypt(Some(KEY), &tampered).is_err());
+    }
+
+    #[test]
+    fn plaintext_values_pass_through() {
+        assert_eq!(decrypt(None, "plain-key").unwrap(), "plain-key");
+    }
+
+    #[test]
+    fn key_must_be_32_bytes() {
+        assert!(encrypt("c2hvcnQ=", "secret").is_err());
+    }
+}
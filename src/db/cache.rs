@@ -0,0 +1,172 @@
+use deadpool_postgres::Object;
+use futures::{stream, StreamExt};
+use log::{info, error, warn};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tokio_postgres::AsyncMessage;
+
+use super::fetch::{fetch_provider_credentials, fetch_source_connection, fetch_tenants, SourceConnection, Tenant};
+
+/// Channel notified by the triggers in `migrations/main/notify_source_connections.sql`
+/// and, for `provider_credentials`, by the logs database migrations.
+pub const SOURCE_CHANNEL: &str = "source_connections_changed";
+
+/// In-memory cache of source connection lookups. Unknown sources are cached too, for a
+/// shorter time, so a misconfigured number does not hit the database on every click.
+/// `generation` is bumped on every invalidation so a lookup that started before it does not
+/// put the stale row it read back into the cache.
+pub struct SourceCache {
+    ttl: Duration,
+    negative_ttl: Duration,
+    generation: AtomicU64,
+    entries: Mutex<HashMap<String, (Instant, Option<SourceConnection>)>>,
+}
+
+impl SourceCache {
+    pub fn new(ttl: Duration, negative_ttl: Duration) -> Self {
+        SourceCache { ttl, negative_ttl, generation: AtomicU64::new(0), entries: Mutex::new(HashMap::new()) }
+    }
+
+    fn cached(&self, source: &str) -> Option<Option<SourceConnection>> {
+        let entries = self.entries.lock().expect("source cache lock poisoned");
+        let (stored_at, entry) = entries.get(source)?;
+        let ttl = if entry.is_some() { self.ttl } else { self.negative_ttl };
+        if stored_at.elapsed() < ttl { Some(entry.clone()) } else { None }
+    }
+
+    pub async fn get(&self, client: &Object, client_logs: &Object, source: &str) -> Result<Option<SourceConnection>, tokio_postgres::Error> {
+        if let Some(entry) = self.cached(source) {
+            info!("Source connection for {} served from cache", source);
+            return Ok(entry);
+        }
+
+        let generation = self.generation.load(Ordering::Acquire);
+        let mut entry = fetch_source_connection(client, source).await?;
+        if let Some(connection) = entry.as_mut() {
+            connection.credentials = fetch_provider_credentials(client_logs, source).await?;
+        }
+        let mut entries = self.entries.lock().expect("source cache lock poisoned");
+        if self.generation.load(Ordering::Acquire) == generation {
+            entries.insert(source.to_string(), (Instant::now(), entry.clone()));
+        }
+        Ok(entry)
+    }
+
+    pub fn invalidate_all(&self) {
+        let mut entries = self.entries.lock().expect("source cache lock poisoned");
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        info!("Invalidating {} cached source connection(s)", entries.len());
+        entries.clear();
+    }
+}
+
+/// The tenant table is small, so it is loaded whole and matched in memory. Invalidation
+/// works like `SourceCache`'s.
+pub struct TenantCache {
+    ttl: Duration,
+    generation: AtomicU64,
+    loaded: Mutex<Option<(Instant, Arc<Vec<Tenant>>)>>,
+}
+
+impl TenantCache {
+    pub fn new(ttl: Duration) -> Self {
+        TenantCache { ttl, generation: AtomicU64::new(0), loaded: Mutex::new(None) }
+    }
+
+    fn cached(&self) -> Option<Arc<Vec<Tenant>>> {
+        let loaded = self.loaded.lock().expect("tenant cache lock poisoned");
+        let (stored_at, tenants) = loaded.as_ref()?;
+        if stored_at.elapsed() < self.ttl { Some(tenants.clone()) } else { None }
+    }
+
+    async fn all(&self, client_logs: &Object) -> Result<Arc<Vec<Tenant>>, tokio_postgres::Error> {
+        if let Some(tenants) = self.cached() {
+            return Ok(tenants);
+        }
+        let generation = self.generation.load(Ordering::Acquire);
+        let tenants = Arc::new(fetch_tenants(client_logs).await?);
+        let mut loaded = self.loaded.lock().expect("tenant cache lock poisoned");
+        if self.generation.load(Ordering::Acquire) == generation {
+            *loaded = Some((Instant::now(), tenants.clone()));
+        }
+        Ok(tenants)
+    }
+
+    /// Finds the tenant owning a click: a `gs_app_id` match wins over a source number match.
+    pub async fn resolve(&self, client_logs: &Object, app_id: Option<&str>, source: &str) -> Result<Option<Tenant>, tokio_postgres::Error> {
+        let tenants = self.all(client_logs).await?;
+        let by_app = app_id.and_then(|app| tenants.iter().find(|t| t.app_id.as_deref() == Some(app)));
+        let tenant = by_app.or_else(|| tenants.iter().find(|t| t.sources.iter().any(|s| s == source)));
+        Ok(tenant.cloned())
+    }
+
+    pub async fn get(&self, client_logs: &Object, id: &str) -> Result<Option<Tenant>, tokio_postgres::Error> {
+        Ok(self.all(client_logs).await?.iter().find(|t| t.id == id).cloned())
+    }
+
+    /// Every tenant with its own logs database, for migrations and schema checks.
+    pub async fn logs_db_urls(&self, client_logs: &Object) -> Result<Vec<(String, String)>, tokio_postgres::Error> {
+        Ok(self
+            .all(client_logs)
+            .await?
+            .iter()
+            .filter_map(|t| t.logs_db_url.clone().map(|url| (t.id.clone(), url)))
+            .collect())
+    }
+
+    pub fn invalidate_all(&self) {
+        let mut loaded = self.loaded.lock().expect("tenant cache lock poisoned");
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        info!("Invalidating cached tenants");
+        *loaded = None;
+    }
+}
+
+/// Keeps a dedicated connection to a database listening on `SOURCE_CHANNEL` and calls
+/// `on_change` on every notification. Reconnects forever; `on_change` also runs after a
+/// reconnect since notifications may have been missed.
+pub async fn listen_for_changes(db_url: String, on_change: impl Fn()) {
+    loop {
+        match listen_once(&db_url, &on_change).await {
+            Ok(_) => warn!("Source change listener connection closed"),
+            Err(e) => error!("Source change listener failed: {}", e),
+        }
+        on_change();
+        sleep(Duration::from_secs(5)).await;
+    }
+}
+
+async fn listen_once(db_url: &str, on_change: &impl Fn()) -> Result<(), Box<dyn std::error::Error>> {
+    let (client, mut connection) = tokio_postgres::connect(db_url, tokio_postgres::NoTls).await?;
+    let (tx, mut notifications) = tokio::sync::mpsc::unbounded_channel();
+
+    let driver = tokio::spawn(async move {
+        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(AsyncMessage::Notification(notification)) => {
+                    let _ = tx.send(notification);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!("Source change listener connection error: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    client.batch_execute(&format!("LISTEN {}", SOURCE_CHANNEL)).await?;
+    info!("Listening for source connection changes on {}", SOURCE_CHANNEL);
+
+    while let Some(notification) = notifications.recv().await {
+        info!("Source connections changed ({}), clearing cache", notification.payload());
+        on_change();
+    }
+
+    driver.abort();
+    Ok(())
+}
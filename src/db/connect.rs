@@ -6,9 +6,11 @@ use std::time::Duration;
 pub async fn create_pool(url: &str) -> Result<Pool, Box<dyn std::error::Error>> {    
     let mut cfg: Config = url.parse()?;
     
+    cfg.connect_timeout(Duration::from_secs(10));
     cfg.keepalives_idle(Duration::from_secs(30));
     cfg.keepalives_interval(Duration::from_secs(10));
     cfg.keepalives_retries(5);
+    cfg.options("-c statement_timeout=30s -c idle_in_transaction_session_timeout=30s");
     
     let mgr = Manager::new(cfg, tokio_postgres::NoTls);
     let pool = Pool::builder(mgr)
@@ -1,25 +1,34 @@
 use tokio_postgres::Error;
 use log::{info, error};
 use deadpool_postgres;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use std::collections::HashMap;
 
-pub async fn fetch_uuid(
-    client: &deadpool_postgres::Object,
-    source: &str
-) -> Result<Option<String>, Error> {
-    info!("Attempting to fetch UUID from database for source: {}", source);
+/// Everything needed to reply through a source number, resolved from `conexoes` and `parametros`.
+#[derive(Debug, Clone)]
+pub struct SourceConnection {
+    pub source: String,
+    pub uuid: String,
+    pub source_name: String,
+    pub credentials: Option<ProviderCredentials>,
+}
 
-    if let Err(e) = client.execute("SET statement_timeout = '30s'", &[]).await {
-        error!("Failed to set statement_timeout: {}", e);
-        return Err(e);
-    }
+/// Per-source overrides from `provider_credentials` in the logs database.
+#[derive(Debug, Clone)]
+pub struct ProviderCredentials {
+    pub api_key: Option<String>,
+    pub app_name: Option<String>,
+    pub endpoint: Option<String>,
+}
 
-    if let Err(e) = client.execute("SET idle_in_transaction_session_timeout = '30s'", &[]).await {
-        error!("Failed to set idle_in_transaction_session_timeout: {}", e);
-        return Err(e);
-    }
+pub async fn fetch_source_connection(
+    client: &deadpool_postgres::Object,
+    source: &str
+) -> Result<Option<SourceConnection>, Error> {
+    info!("Attempting to fetch source connection from database for source: {}", source);
 
     let row = match client.query_opt(
-        "SELECT p.uuid FROM parametros p JOIN conexoes c ON p.source_name = c.source_name WHERE c.source = $1",
+        "SELECT c.source, p.uuid, p.source_name FROM conexoes c JOIN parametros p ON p.source_name = c.source_name WHERE c.source = $1 LIMIT 1",
         &[&source]
     ).await {
         Ok(row) => row,
@@ -31,40 +40,222 @@ pub async fn fetch_uuid(
 
     match row {
         Some(row) => {
-            match row.try_get::<_, String>("uuid") {
-                Ok(uuid) => Ok(Some(uuid)),
+            let connection = (|| Ok::<_, Error>(SourceConnection {
+                source: row.try_get("source")?,
+                uuid: row.try_get("uuid")?,
+                source_name: row.try_get("source_name")?,
+                credentials: None,
+            }))();
+            match connection {
+                Ok(connection) => Ok(Some(connection)),
                 Err(e) => {
-                    error!("Failed to extract uuid from row: {}", e);
+                    error!("Failed to extract source connection from row: {}", e);
                     Ok(None)
                 }
             }
         },
         None => {
-            info!("No uuid found for source: {}", source);
+            info!("No source connection found for source: {}", source);
             Ok(None)
         }
     }
 }
 
-pub async fn fetch_conn(
+pub async fn fetch_provider_credentials(
     client: &deadpool_postgres::Object,
     source: &str
+) -> Result<Option<ProviderCredentials>, Error> {
+    info!("Attempting to fetch provider credentials for source: {}", source);
+
+    let row = match client.query_opt(
+        "SELECT api_key, app_name, endpoint FROM provider_credentials WHERE source = $1",
+        &[&source]
+    ).await {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Failed to execute SELECT query: {}", e);
+            return Err(e);
+        }
+    };
+
+    match row {
+        Some(row) => Ok(Some(ProviderCredentials {
+            api_key: row.try_get("api_key").unwrap_or(None),
+            app_name: row.try_get("app_name").unwrap_or(None),
+            endpoint: row.try_get("endpoint").unwrap_or(None),
+        })),
+        None => {
+            info!("No provider credentials for source {}, using defaults", source);
+            Ok(None)
+        }
+    }
+}
+
+/// A partner brand served by this consumer, from `tenants` and `tenant_replies` in the logs database.
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub id: String,
+    pub app_id: Option<String>,
+    pub sources: Vec<String>,
+    pub logs_db_url: Option<String>,
+    pub credentials: ProviderCredentials,
+    pub enabled: bool,
+    pub replies: HashMap<String, TenantReply>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TenantReply {
+    pub reply: Option<String>,
+    pub log_message: String,
+}
+
+pub async fn fetch_tenants(client: &deadpool_postgres::Object) -> Result<Vec<Tenant>, Error> {
+    info!("Attempting to fetch tenants");
+
+    let rows = match client.query(
+        "SELECT id, app_id, sources, logs_db_url, api_key, app_name, endpoint, enabled FROM tenants",
+        &[]
+    ).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to execute SELECT query: {}", e);
+            return Err(e);
+        }
+    };
+
+    let mut tenants: Vec<Tenant> = rows
+        .iter()
+        .map(|row| Ok(Tenant {
+            id: row.try_get("id")?,
+            app_id: row.try_get("app_id")?,
+            sources: row.try_get("sources")?,
+            logs_db_url: row.try_get("logs_db_url")?,
+            credentials: ProviderCredentials {
+                api_key: row.try_get("api_key")?,
+                app_name: row.try_get("app_name")?,
+                endpoint: row.try_get("endpoint")?,
+            },
+            enabled: row.try_get("enabled")?,
+            replies: HashMap::new(),
+        }))
+        .collect::<Result<_, Error>>()?;
+
+    let replies = match client.query("SELECT tenant_id, tipo, reply, log_message FROM tenant_replies", &[]).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to execute SELECT query: {}", e);
+            return Err(e);
+        }
+    };
+
+    for row in replies.iter() {
+        let tenant_id: String = row.try_get("tenant_id")?;
+        if let Some(tenant) = tenants.iter_mut().find(|t| t.id == tenant_id) {
+            tenant.replies.insert(row.try_get("tipo")?, TenantReply {
+                reply: row.try_get("reply")?,
+                log_message: row.try_get("log_message")?,
+            });
+        }
+    }
+
+    info!("Fetched {} tenant(s)", tenants.len());
+    Ok(tenants)
+}
+
+pub async fn fetch_source_by_app(
+    client: &deadpool_postgres::Object,
+    app: &str
 ) -> Result<Option<String>, Error> {
-    info!("Attempting to fetch conn from database for source: {}", source);
+    info!("Attempting to fetch source number for app: {}", app);
 
-    if let Err(e) = client.execute("SET statement_timeout = '30s'", &[]).await {
-        error!("Failed to set statement_timeout: {}", e);
-        return Err(e);
+    let row = match client.query_opt(
+        "SELECT source FROM conexoes WHERE source_name = $1 LIMIT 1",
+        &[&app]
+    ).await {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Failed to execute SELECT query: {}", e);
+            return Err(e);
+        }
+    };
+
+    match row {
+        Some(row) => match row.try_get::<_, String>("source") {
+            Ok(source) => Ok(Some(source)),
+            Err(e) => {
+                error!("Failed to extract source from row: {}", e);
+                Ok(None)
+            }
+        },
+        None => {
+            info!("No source found for app: {}", app);
+            Ok(None)
+        }
     }
+}
+
+/// An outbound template message as recorded by the sending system.
+#[derive(Debug, Clone)]
+pub struct SentTemplate {
+    pub template_name: String,
+    pub campaign_id: Option<String>,
+    pub sent_at_epoch: Option<i64>,
+}
+
+pub async fn fetch_sent_template(
+    client: &deadpool_postgres::Object,
+    message_ids: &[&str]
+) -> Result<Option<SentTemplate>, Error> {
+    info!("Attempting to fetch sent template for message ids: {:?}", message_ids);
+
+    let row = match client.query_opt(
+        "SELECT template_name, campaign_id, EXTRACT(EPOCH FROM sent_at)::BIGINT AS sent_at_epoch
+         FROM template_messages WHERE message_id = ANY($1) LIMIT 1",
+        &[&message_ids]
+    ).await {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Failed to execute SELECT query: {}", e);
+            return Err(e);
+        }
+    };
 
-    if let Err(e) = client.execute("SET idle_in_transaction_session_timeout = '30s'", &[]).await {
-        error!("Failed to set idle_in_transaction_session_timeout: {}", e);
-        return Err(e);
+    match row {
+        Some(row) => match row.try_get::<_, String>("template_name") {
+            Ok(template_name) => Ok(Some(SentTemplate {
+                template_name,
+                campaign_id: row.try_get("campaign_id").unwrap_or(None),
+                sent_at_epoch: row.try_get("sent_at_epoch").unwrap_or(None),
+            })),
+            Err(e) => {
+                error!("Failed to extract template_name from row: {}", e);
+                Ok(None)
+            }
+        },
+        None => {
+            info!("No sent template found for message ids: {:?}", message_ids);
+            Ok(None)
+        }
     }
+}
+
+pub async fn fetch_route_tipo(
+    client: &deadpool_postgres::Object,
+    tenant_id: Option<&str>,
+    template_name: Option<&str>,
+    payload: &str
+) -> Result<Option<String>, Error> {
+    info!("Attempting to fetch route for tenant {:?}, template {:?} and payload {}", tenant_id, template_name, payload);
 
     let row = match client.query_opt(
-        "SELECT source_name FROM parametros WHERE source_name IN (SELECT source_name FROM conexoes WHERE source = $1)",
-        &[&source]
+        "SELECT tipo FROM button_routes
+         WHERE active
+           AND (payload = $2 OR payload IS NULL)
+           AND (template_name = $1 OR template_name IS NULL)
+           AND (tenant_id = $3 OR tenant_id IS NULL)
+         ORDER BY (tenant_id IS NOT NULL) DESC, (payload IS NOT NULL) DESC, (template_name IS NOT NULL) DESC
+         LIMIT 1",
+        &[&template_name, &payload, &tenant_id]
     ).await {
         Ok(row) => row,
         Err(e) => {
@@ -74,18 +265,138 @@ pub async fn fetch_conn(
     };
 
     match row {
-        Some(row) => {
-            match row.try_get::<_, String>("source_name") {
-                Ok(source_name) => Ok(Some(source_name)),
-                Err(e) => {
-                    error!("Failed to extract source_name from row: {}", e);
-                    Ok(None)
-                }
+        Some(row) => match row.try_get::<_, String>("tipo") {
+            Ok(tipo) => Ok(Some(tipo)),
+            Err(e) => {
+                error!("Failed to extract tipo from row: {}", e);
+                Ok(None)
             }
         },
         None => {
-            info!("No source_name found for source: {}", source);
+            info!("No route rule matched payload {}", payload);
             Ok(None)
         }
     }
-}
\ No newline at end of file
+}
+
+pub async fn fetch_opted_out(
+    client: &deadpool_postgres::Object,
+    num: &str
+) -> Result<bool, Error> {
+    info!("Checking opt-out list for {}", num);
+
+    match client.query_opt("SELECT 1 FROM opt_outs WHERE num = $1", &[&num]).await {
+        Ok(row) => Ok(row.is_some()),
+        Err(e) => {
+            error!("Failed to execute SELECT query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Whether `num` is in the latest completed Não Me Perturbe import.
+pub async fn fetch_nmp_listed(
+    client: &deadpool_postgres::Object,
+    num: &str
+) -> Result<bool, Error> {
+    info!("Checking Não Me Perturbe list for {}", num);
+
+    match client.query_opt(
+        "SELECT 1 FROM nmp_numbers
+         WHERE num = $1
+           AND import_id = (SELECT MAX(id) FROM nmp_imports WHERE completed)",
+        &[&num]
+    ).await {
+        Ok(row) => Ok(row.is_some()),
+        Err(e) => {
+            error!("Failed to execute SELECT query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Allowed sending hours for a source from `send_windows`, in America/Sao_Paulo time.
+#[derive(Debug, Clone)]
+pub struct SendWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub days: Vec<i16>,
+}
+
+pub async fn fetch_send_window(
+    client: &deadpool_postgres::Object,
+    source: &str
+) -> Result<Option<SendWindow>, Error> {
+    info!("Attempting to fetch send window for source: {}", source);
+
+    match client.query_opt("SELECT start_time, end_time, days FROM send_windows WHERE source = $1", &[&source]).await {
+        Ok(row) => Ok(row.map(|row| SendWindow {
+            start: row.get("start_time"),
+            end: row.get("end_time"),
+            days: row.get("days"),
+        })),
+        Err(e) => {
+            error!("Failed to execute SELECT query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+pub async fn fetch_holidays(
+    client: &deadpool_postgres::Object,
+    from: NaiveDate,
+    to: NaiveDate
+) -> Result<Vec<NaiveDate>, Error> {
+    match client.query("SELECT day FROM holidays WHERE day BETWEEN $1 AND $2", &[&from, &to]).await {
+        Ok(rows) => Ok(rows.iter().map(|row| row.get("day")).collect()),
+        Err(e) => {
+            error!("Failed to execute SELECT query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+pub async fn fetch_last_inbound(
+    client: &deadpool_postgres::Object,
+    num: &str
+) -> Result<Option<DateTime<Utc>>, Error> {
+    match client.query_opt("SELECT last_inbound_at FROM customer_activity WHERE num = $1", &[&num]).await {
+        Ok(row) => Ok(row.map(|row| row.get("last_inbound_at"))),
+        Err(e) => {
+            error!("Failed to execute SELECT query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// The approved template configured for a route in `route_templates`, with unrendered params.
+#[derive(Debug, Clone)]
+pub struct RouteTemplate {
+    pub template_id: String,
+    pub params: Vec<String>,
+}
+
+pub async fn fetch_route_template(
+    client: &deadpool_postgres::Object,
+    tenant_id: Option<&str>,
+    tipo: &str
+) -> Result<Option<RouteTemplate>, Error> {
+    info!("Attempting to fetch template for tenant {:?} and route {}", tenant_id, tipo);
+
+    match client.query_opt(
+        "SELECT template_id, params FROM route_templates
+         WHERE tipo = $1 AND (tenant_id = $2 OR tenant_id IS NULL)
+         ORDER BY (tenant_id IS NOT NULL) DESC
+         LIMIT 1",
+        &[&tipo, &tenant_id]
+    ).await {
+        Ok(row) => Ok(row.map(|row| RouteTemplate {
+            template_id: row.get("template_id"),
+            params: row.get("params"),
+        })),
+        Err(e) => {
+            error!("Failed to execute SELECT query: {}", e);
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Object;
+use log::{info, error};
+use tokio_postgres::Error;
+
+/// A claimed row of `follow_ups`.
+#[derive(Debug, Clone)]
+pub struct FollowUp {
+    pub id: i64,
+    pub num: String,
+    pub source: String,
+    pub tenant_id: Option<String>,
+    pub tipo: String,
+    pub reminders_sent: i32,
+    /// Failed attempts at the current reminder.
+    pub attempts: i32,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// How long a claimed follow-up is hidden from other runs while its reminder is sent.
+const CLAIM_FOR: &str = "10 minutes";
+
+/// Starts a follow-up for `num`, replacing any active one (a new question supersedes it).
+pub async fn insert_follow_up(
+    client: &Object,
+    num: &str,
+    source: &str,
+    tenant_id: Option<&str>,
+    tipo: &str,
+    next_at: DateTime<Utc>
+) -> Result<(), Error> {
+    info!("Scheduling {} follow-up for {} at {}", tipo, num, next_at);
+
+    client.execute(
+        "UPDATE follow_ups SET status = 'superseded', closed_at = now() WHERE num = $1 AND status = 'active'",
+        &[&num]
+    ).await?;
+    match client.execute(
+        "INSERT INTO follow_ups (num, source, tenant_id, tipo, next_at) VALUES ($1, $2, $3, $4, $5)",
+        &[&num, &source, &tenant_id, &tipo, &next_at]
+    ).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Failed to execute INSERT query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Cancels the active follow-up of `num`, if any. Returns whether one was cancelled.
+pub async fn cancel_follow_ups(client: &Object, num: &str) -> Result<bool, Error> {
+    match client.execute(
+        "UPDATE follow_ups SET status = 'answered', closed_at = now() WHERE num = $1 AND status = 'active'",
+        &[&num]
+    ).await {
+        Ok(updated) => Ok(updated > 0),
+        Err(e) => {
+            error!("Failed to execute UPDATE query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Claims due follow-ups by pushing `next_at` forward, so a crash mid-send only delays them.
+pub async fn claim_due_follow_ups(client: &Object, limit: i64) -> Result<Vec<FollowUp>, Error> {
+    let rows = match client.query(
+        &format!(
+            "UPDATE follow_ups SET next_at = now() + INTERVAL '{}'
+             WHERE id IN (
+                 SELECT id FROM follow_ups
+                 WHERE status = 'active' AND next_at <= now()
+                 ORDER BY next_at
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, num, source, tenant_id, tipo, reminders_sent, attempts, created_at",
+            CLAIM_FOR
+        ),
+        &[&limit]
+    ).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to execute UPDATE query: {}", e);
+            return Err(e);
+        }
+    };
+
+    Ok(rows
+        .iter()
+        .map(|row| FollowUp {
+            id: row.get("id"),
+            num: row.get("num"),
+            source: row.get("source"),
+            tenant_id: row.get("tenant_id"),
+            tipo: row.get("tipo"),
+            reminders_sent: row.get("reminders_sent"),
+            attempts: row.get("attempts"),
+            created_at: row.get("created_at"),
+        })
+        .collect())
+}
+
+/// Records a sent reminder. With `next_at` the follow-up stays active, otherwise it is done.
+pub async fn record_reminder(client: &Object, id: i64, next_at: Option<DateTime<Utc>>) -> Result<(), Error> {
+    match next_at {
+        Some(next_at) => client.execute(
+            "UPDATE follow_ups SET reminders_sent = reminders_sent + 1, last_reminder_at = now(), next_at = $2, attempts = 0, last_error = NULL
+             WHERE id = $1 AND status = 'active'",
+            &[&id, &next_at]
+        ).await?,
+        None => client.execute(
+            "UPDATE follow_ups SET reminders_sent = reminders_sent + 1, last_reminder_at = now(), status = 'done', closed_at = now()
+             WHERE id = $1 AND status = 'active'",
+            &[&id]
+        ).await?,
+    };
+    Ok(())
+}
+
+/// Moves an active follow-up to `next_at` without counting a reminder. An `error` counts as
+/// a failed attempt.
+pub async fn reschedule_follow_up(client: &Object, id: i64, next_at: DateTime<Utc>, error: Option<&str>) -> Result<(), Error> {
+    client.execute(
+        "UPDATE follow_ups SET next_at = $2, last_error = $3, attempts = attempts + CASE WHEN $3::text IS NULL THEN 0 ELSE 1 END
+         WHERE id = $1 AND status = 'active'",
+        &[&id, &next_at, &error]
+    ).await?;
+    Ok(())
+}
+
+pub async fn close_follow_up(client: &Object, id: i64, status: &str, reason: &str) -> Result<(), Error> {
+    client.execute(
+        "UPDATE follow_ups SET status = $2, last_error = $3, closed_at = now() WHERE id = $1 AND status = 'active'",
+        &[&id, &status, &reason]
+    ).await?;
+    Ok(())
+}
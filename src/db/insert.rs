@@ -1,34 +1,182 @@
+use chrono::{DateTime, Utc};
 use tokio_postgres::Error;
 use log::{info, error};
 use deadpool_postgres;
+use crate::process::audit::{compress_payload, AuditRecord};
+use crate::process::shadow::ShadowRecord;
+use super::log_writer::LogRecord;
 
-pub async fn insert_log(
+/// Used by `db::outbox`, which writes the row in the same transaction as the reply.
+pub const INSERT_LOG: &str = "INSERT INTO \"button-answers\" (num, mensagem, resposta_cliente, tipo, button_payload, template_name, campaign_id, time_to_click_secs) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)";
+
+/// Writes a batch of logs in one statement, each with the time it was recorded. Called by
+/// `db::log_writer`.
+pub async fn insert_logs(
     client: &deadpool_postgres::Object,
-    num: &str,
-    msg: &str,
-    resp_cliente: &str,
-    tipo: &str
+    logs: &[LogRecord]
+) -> Result<u64, Error> {
+    info!("Attempting to insert {} log(s) into the database:", logs.len());
+
+    let column = |f: fn(&LogRecord) -> &str| logs.iter().map(f).collect::<Vec<_>>();
+    let nums = column(|l| &l.num);
+    let mensagens = column(|l| &l.mensagem);
+    let respostas = column(|l| &l.resposta_cliente);
+    let tipos = column(|l| &l.tipo);
+    let payloads = column(|l| &l.button_payload);
+    let recorded_at = column(|l| &l.recorded_at);
+    let template_names: Vec<Option<&str>> = logs.iter().map(|l| l.template_name.as_deref()).collect();
+    let campaign_ids: Vec<Option<&str>> = logs.iter().map(|l| l.campaign_id.as_deref()).collect();
+    let times_to_click: Vec<Option<i64>> = logs.iter().map(|l| l.time_to_click_secs).collect();
+
+    match client.execute(
+        "INSERT INTO \"button-answers\" (num, mensagem, resposta_cliente, tipo, button_payload, template_name, campaign_id, time_to_click_secs, created_at)
+         SELECT num, mensagem, resposta_cliente, tipo, button_payload, template_name, campaign_id, time_to_click_secs, created_at::timestamptz
+         FROM UNNEST($1::VARCHAR[], $2::TEXT[], $3::VARCHAR[], $4::VARCHAR[], $5::VARCHAR[], $6::VARCHAR[], $7::VARCHAR[], $8::BIGINT[], $9::TEXT[])
+             AS t(num, mensagem, resposta_cliente, tipo, button_payload, template_name, campaign_id, time_to_click_secs, created_at)",
+        &[&nums, &mensagens, &respostas, &tipos, &payloads, &template_names, &campaign_ids, &times_to_click, &recorded_at]
+    ).await {
+        Ok(inserted) => Ok(inserted),
+        Err(e) => {
+            error!("Failed to execute INSERT query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Writes a log back from the local spool, keeping its original time. Returns `false` when
+/// an earlier, interrupted drain already wrote it.
+pub async fn insert_spooled_log(
+    client: &deadpool_postgres::Object,
+    log: &LogRecord
+) -> Result<bool, Error> {
+    match client.execute(
+        "INSERT INTO \"button-answers\" (num, mensagem, resposta_cliente, tipo, button_payload, template_name, campaign_id, time_to_click_secs, spool_id, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10::text::timestamptz)
+         ON CONFLICT (spool_id) WHERE spool_id IS NOT NULL DO NOTHING",
+        &[&log.num, &log.mensagem, &log.resposta_cliente, &log.tipo, &log.button_payload, &log.template_name, &log.campaign_id, &log.time_to_click_secs, &log.spool_id, &log.recorded_at]
+    ).await {
+        Ok(inserted) => Ok(inserted == 1),
+        Err(e) => {
+            error!("Failed to execute INSERT query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+pub async fn insert_shadow_log(
+    client: &deadpool_postgres::Object,
+    record: &ShadowRecord<'_>
 ) -> Result<(), Error> {
-    info!("Attempting to insert log into the database:");
+    info!("Attempting to insert shadow log into the database:");
 
     if let Err(e) = client.execute("SET statement_timeout = '30s'", &[]).await {
         error!("Failed to set statement_timeout: {}", e);
         return Err(e);
     }
 
-    if let Err(e) = client.execute("SET idle_in_transaction_session_timeout = '30s'", &[]).await {
-        error!("Failed to set idle_in_transaction_session_timeout: {}", e);
-        return Err(e);
+    match client.execute(
+        "INSERT INTO \"button-answers-shadow\" (num, source, source_name, resposta_cliente, tipo, mensagem_enviada, mensagem) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        &[&record.num, &record.source, &record.source_name, &record.button, &record.tipo, &record.reply, &record.log_message]
+    ).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Failed to execute INSERT query: {}", e);
+            Err(e)
+        }
     }
+}
+
+pub async fn insert_audit(
+    client: &deadpool_postgres::Object,
+    audit: &AuditRecord,
+    compress: bool
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Attempting to insert audit record into the database:");
+
+    let (payload, payload_gz) = if compress {
+        (None, Some(compress_payload(&audit.payload)?))
+    } else {
+        (Some(String::from_utf8_lossy(&audit.payload).into_owned()), None)
+    };
+    let click = audit.click.as_ref();
 
     match client.execute(
-        "INSERT INTO \"button-answers\" (num, mensagem, resposta_cliente, tipo) VALUES ($1, $2, $3, $4)",
-        &[&num, &msg, &resp_cliente, &tipo]
+        "INSERT INTO \"button-answers-audit\" (num, source, message_id, context_id, gs_id, meta_msg_id, message_timestamp, button_text, button_payload, tipo, provider_response, duration_ms, error, payload, payload_gz, provider, app_id, phone_number_id, contact_name, tenant_id, processing_lag_secs) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21)",
+        &[
+            &click.map(|c| &c.from),
+            &click.map(|c| &c.source),
+            &click.map(|c| &c.message_id),
+            &click.map(|c| &c.context_id),
+            &click.map(|c| &c.gs_id),
+            &click.map(|c| &c.meta_msg_id),
+            &click.map(|c| &c.timestamp),
+            &click.map(|c| &c.button_text),
+            &click.map(|c| &c.button_payload),
+            &audit.tipo,
+            &audit.provider_response,
+            &audit.duration_ms,
+            &audit.error,
+            &payload,
+            &payload_gz,
+            &click.map(|c| format!("{:?}", c.provider)),
+            &click.and_then(|c| c.app_id.as_ref()),
+            &click.and_then(|c| c.phone_number_id.as_ref()),
+            &click.and_then(|c| c.contact_name.as_ref()),
+            &audit.tenant_id,
+            &audit.lag_secs,
+        ]
     ).await {
         Ok(_) => Ok(()),
         Err(e) => {
             error!("Failed to execute INSERT query: {}", e);
-            return Err(e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+/// Adds a number to `opt_outs`. Returns `false` when it was already there, so the
+/// confirmation is only sent once.
+pub async fn insert_opt_out(
+    client: &deadpool_postgres::Object,
+    num: &str,
+    source: &str,
+    tenant_id: Option<&str>,
+    keyword: &str
+) -> Result<bool, Error> {
+    info!("Attempting to insert opt-out for {} into the database:", num);
+
+    match client.execute(
+        "INSERT INTO opt_outs (num, source, tenant_id, keyword) VALUES ($1, $2, $3, $4) ON CONFLICT (num) DO NOTHING",
+        &[&num, &source, &tenant_id, &keyword]
+    ).await {
+        Ok(inserted) => Ok(inserted == 1),
+        Err(e) => {
+            error!("Failed to execute INSERT query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Records an inbound message and returns the customer's latest inbound time, which may be
+/// later than `at` when an older click is replayed.
+pub async fn upsert_customer_activity(
+    client: &deadpool_postgres::Object,
+    num: &str,
+    source: &str,
+    at: DateTime<Utc>
+) -> Result<DateTime<Utc>, Error> {
+    match client.query_one(
+        "INSERT INTO customer_activity (num, source, last_inbound_at) VALUES ($1, $2, $3)
+         ON CONFLICT (num) DO UPDATE SET
+             source = EXCLUDED.source,
+             last_inbound_at = GREATEST(customer_activity.last_inbound_at, EXCLUDED.last_inbound_at)
+         RETURNING last_inbound_at",
+        &[&num, &source, &at]
+    ).await {
+        Ok(row) => Ok(row.get("last_inbound_at")),
+        Err(e) => {
+            error!("Failed to execute INSERT query: {}", e);
+            Err(e)
         }
     }
 }
@@ -0,0 +1,179 @@
+use chrono::Utc;
+use log::{info, error, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+use super::pools::Pools;
+use crate::config::config::EnvVars;
+use crate::process::process::Attribution;
+use crate::spool::spool::Spool;
+
+/// A `"button-answers"` row waiting to be written, by the writer or later from the local
+/// spool. The tenant, not its database URL, is kept so no credentials end up on disk;
+/// `spool_id` makes the spool drain idempotent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub spool_id: String,
+    #[serde(alias = "spooled_at")]
+    pub recorded_at: String,
+    pub tenant_id: Option<String>,
+    pub num: String,
+    pub mensagem: String,
+    pub resposta_cliente: String,
+    pub tipo: String,
+    pub button_payload: String,
+    pub template_name: Option<String>,
+    pub campaign_id: Option<String>,
+    pub time_to_click_secs: Option<i64>,
+}
+
+impl LogRecord {
+    pub fn new(message_id: &str, tenant_id: Option<&str>, num: &str, mensagem: &str, resposta_cliente: &str, tipo: &str, attribution: &Attribution) -> Self {
+        let recorded_at = Utc::now();
+        LogRecord {
+            spool_id: format!("{}-{}", recorded_at.timestamp_micros(), message_id),
+            recorded_at: recorded_at.to_rfc3339(),
+            tenant_id: tenant_id.map(str::to_string),
+            num: num.to_string(),
+            mensagem: mensagem.to_string(),
+            resposta_cliente: resposta_cliente.to_string(),
+            tipo: tipo.to_string(),
+            button_payload: attribution.button_payload.clone(),
+            template_name: attribution.template_name.clone(),
+            campaign_id: attribution.campaign_id.clone(),
+            time_to_click_secs: attribution.time_to_click_secs,
+        }
+    }
+}
+
+/// How long shutdown waits for the writer when neither the database nor the spool takes rows.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+enum Command {
+    Write { logs_db_url: String, log: Box<LogRecord> },
+    Flush(oneshot::Sender<()>),
+}
+
+/// Buffers `"button-answers"` rows and writes them in multi-row INSERTs, every
+/// `LOG_BATCH_SIZE` rows or `LOG_FLUSH_INTERVAL_MS`, whichever comes first. At most
+/// `LOG_WRITER_CAPACITY` rows wait in the queue; `write` blocks beyond that. Batches the
+/// database refuses go to the local spool; rows the spool refuses too (it is full) stay in
+/// the buffer and are retried, and while they fill it no new rows are accepted.
+pub struct LogWriter {
+    sender: mpsc::Sender<Command>,
+}
+
+impl LogWriter {
+    /// Spawns the writer task, which keeps its own connections to the logs databases.
+    pub fn start(env_vars: &EnvVars, spool: Arc<Spool>) -> Self {
+        let capacity = env_vars.log_writer_capacity.max(1);
+        let (sender, receiver) = mpsc::channel(capacity);
+        tokio::spawn(run(
+            receiver,
+            env_vars.log_batch_size.max(1),
+            capacity,
+            Duration::from_millis(env_vars.log_flush_interval_ms.max(1)),
+            spool,
+        ));
+        LogWriter { sender }
+    }
+
+    /// Queues a row for `logs_db_url`, waiting while the queue is full.
+    pub async fn write(&self, logs_db_url: &str, log: LogRecord) -> Result<(), Box<dyn std::error::Error>> {
+        self.sender
+            .send(Command::Write { logs_db_url: logs_db_url.to_string(), log: Box::new(log) })
+            .await
+            .map_err(|_| "log writer stopped".into())
+    }
+
+    /// Writes everything queued so far and waits for it, up to `FLUSH_TIMEOUT`. Called before
+    /// the process exits.
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        let flush = async { self.sender.send(Command::Flush(done)).await.is_ok() && flushed.await.is_ok() };
+        match tokio::time::timeout(FLUSH_TIMEOUT, flush).await {
+            Ok(true) => {}
+            Ok(false) => error!("Log writer stopped before flushing"),
+            Err(_) => error!("Log writer did not flush within {:?}, unwritten logs are lost", FLUSH_TIMEOUT),
+        }
+    }
+}
+
+async fn run(
+    mut receiver: mpsc::Receiver<Command>,
+    batch_size: usize,
+    max_buffered: usize,
+    flush_interval: Duration,
+    spool: Arc<Spool>
+) {
+    let pools = Pools::default();
+    let mut buffer: Vec<(String, LogRecord)> = Vec::with_capacity(batch_size);
+    let mut interval = tokio::time::interval(flush_interval);
+
+    loop {
+        tokio::select! {
+            command = receiver.recv(), if buffer.len() < max_buffered => match command {
+                Some(Command::Write { logs_db_url, log }) => {
+                    buffer.push((logs_db_url, *log));
+                    if buffer.len() >= batch_size {
+                        flush(&pools, &spool, &mut buffer).await;
+                    }
+                }
+                Some(Command::Flush(done)) => {
+                    flush(&pools, &spool, &mut buffer).await;
+                    report_unwritten(&buffer);
+                    let _ = done.send(());
+                }
+                None => {
+                    flush(&pools, &spool, &mut buffer).await;
+                    report_unwritten(&buffer);
+                    return;
+                }
+            },
+            _ = interval.tick() => flush(&pools, &spool, &mut buffer).await,
+        }
+    }
+}
+
+/// One INSERT per logs database; rows of a failed INSERT are spooled, and those the spool
+/// refuses are put back in `buffer`.
+async fn flush(pools: &Pools, spool: &Spool, buffer: &mut Vec<(String, LogRecord)>) {
+    if buffer.is_empty() {
+        return;
+    }
+
+    let mut batches: HashMap<String, Vec<LogRecord>> = HashMap::new();
+    for (logs_db_url, log) in buffer.drain(..) {
+        batches.entry(logs_db_url).or_default().push(log);
+    }
+
+    for (logs_db_url, logs) in batches {
+        let inserted = match pools.client(&logs_db_url).await.map_err(|e| e.to_string()) {
+            Ok(client) => super::insert::insert_logs(&client, &logs).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        match inserted {
+            Ok(count) => info!("Wrote {} log(s) to the logs database", count),
+            Err(e) => {
+                warn!("Failed to write {} log(s), spooling them: {}", logs.len(), e);
+                for log in logs {
+                    let spooled = spool.append(&log).await.map_err(|e| e.to_string());
+                    if let Err(e) = spooled {
+                        warn!("Keeping log for {} in memory, the spool refused it: {}", log.num, e);
+                        buffer.push((logs_db_url.clone(), log));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Only reached on shutdown, when neither the database nor the spool took the rows.
+fn report_unwritten(buffer: &[(String, LogRecord)]) {
+    if !buffer.is_empty() {
+        error!("{} log(s) could not be written or spooled and are lost", buffer.len());
+    }
+}
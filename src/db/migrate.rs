@@ -0,0 +1,152 @@
+use deadpool_postgres::Object;
+use log::{info, error, warn};
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Migrations for the tables this service owns in the logs database, applied in order.
+pub const LOGS_MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "button_answers", sql: include_str!("../../migrations/logs/0001_button_answers.sql") },
+    Migration { version: 2, name: "button_answers_shadow", sql: include_str!("../../migrations/logs/0002_button_answers_shadow.sql") },
+    Migration { version: 3, name: "button_answers_audit", sql: include_str!("../../migrations/logs/0003_button_answers_audit.sql") },
+    Migration { version: 4, name: "button_routes", sql: include_str!("../../migrations/logs/0004_button_routes.sql") },
+    Migration { version: 5, name: "button_answers_attribution", sql: include_str!("../../migrations/logs/0005_button_answers_attribution.sql") },
+    Migration { version: 6, name: "button_answers_audit_provider", sql: include_str!("../../migrations/logs/0006_button_answers_audit_provider.sql") },
+    Migration { version: 7, name: "provider_credentials", sql: include_str!("../../migrations/logs/0007_provider_credentials.sql") },
+    Migration { version: 8, name: "tenants", sql: include_str!("../../migrations/logs/0008_tenants.sql") },
+    Migration { version: 9, name: "opt_outs", sql: include_str!("../../migrations/logs/0009_opt_outs.sql") },
+    Migration { version: 10, name: "nao_me_perturbe", sql: include_str!("../../migrations/logs/0010_nao_me_perturbe.sql") },
+    Migration { version: 11, name: "quiet_hours", sql: include_str!("../../migrations/logs/0011_quiet_hours.sql") },
+    Migration { version: 12, name: "follow_ups", sql: include_str!("../../migrations/logs/0012_follow_ups.sql") },
+    Migration { version: 13, name: "service_window", sql: include_str!("../../migrations/logs/0013_service_window.sql") },
+    Migration { version: 14, name: "audit_processing_lag", sql: include_str!("../../migrations/logs/0014_audit_processing_lag.sql") },
+    Migration { version: 15, name: "partner_webhooks", sql: include_str!("../../migrations/logs/0015_partner_webhooks.sql") },
+    Migration { version: 16, name: "outbox", sql: include_str!("../../migrations/logs/0016_outbox.sql") },
+    Migration { version: 17, name: "button_answers_spool", sql: include_str!("../../migrations/logs/0017_button_answers_spool.sql") },
+    Migration { version: 18, name: "button_routes_tenant_rule_idx", sql: include_str!("../../migrations/logs/0018_button_routes_tenant_rule_idx.sql") },
+    Migration { version: 19, name: "follow_up_attempts", sql: include_str!("../../migrations/logs/0019_follow_up_attempts.sql") },
+    Migration { version: 20, name: "send_windows_same_day", sql: include_str!("../../migrations/logs/0020_send_windows_same_day.sql") },
+];
+
+/// Columns the main database must provide; those tables belong to other services.
+pub const MAIN_SCHEMA: &[(&str, &[&str])] = &[
+    ("parametros", &["uuid", "source_name"]),
+    ("conexoes", &["source", "source_name"]),
+];
+
+/// Main database tables the service can do without: clicks are still routed when they are
+/// missing, only campaign attribution is lost.
+pub const OPTIONAL_MAIN_SCHEMA: &[(&str, &[&str])] = &[
+    ("template_messages", &["message_id", "template_name", "campaign_id", "sent_at"]),
+];
+
+pub const LOGS_SCHEMA: &[(&str, &[&str])] = &[
+    ("button-answers", &["num", "mensagem", "resposta_cliente", "tipo", "button_payload", "template_name", "campaign_id", "time_to_click_secs", "spool_id"]),
+    ("button-answers-shadow", &["num", "source", "source_name", "resposta_cliente", "tipo", "mensagem_enviada", "mensagem"]),
+    ("button-answers-audit", &["num", "source", "message_id", "context_id", "gs_id", "meta_msg_id", "message_timestamp", "button_text", "button_payload", "tipo", "provider_response", "duration_ms", "error", "payload", "payload_gz", "provider", "app_id", "phone_number_id", "contact_name", "tenant_id", "processing_lag_secs"]),
+    ("button_routes", &["template_name", "payload", "tipo", "active", "tenant_id"]),
+    ("provider_credentials", &["source", "api_key", "app_name", "endpoint"]),
+    ("tenants", &["id", "app_id", "sources", "logs_db_url", "api_key", "app_name", "endpoint", "enabled"]),
+    ("tenant_replies", &["tenant_id", "tipo", "reply", "log_message"]),
+    ("opt_outs", &["num", "source", "tenant_id", "keyword"]),
+    ("nmp_imports", &["id", "file_name", "row_count", "completed", "imported_at"]),
+    ("nmp_numbers", &["import_id", "num"]),
+    ("send_windows", &["source", "start_time", "end_time", "days"]),
+    ("holidays", &["day"]),
+    ("scheduled_sends", &["id", "num", "source", "tenant_id", "tipo", "reply", "send_after", "status", "attempts", "claimed_at", "sent_at", "provider_response", "last_error", "template_id", "template_params"]),
+    ("follow_ups", &["id", "num", "source", "tenant_id", "tipo", "status", "reminders_sent", "next_at", "last_reminder_at", "last_error", "closed_at"]),
+    ("customer_activity", &["num", "source", "last_inbound_at"]),
+    ("route_templates", &["tenant_id", "tipo", "template_id", "params"]),
+    ("partner_webhooks", &["id", "partner", "source", "url", "secret", "active"]),
+    ("partner_deliveries", &["id", "webhook_id", "event", "payload", "status", "attempts", "next_attempt_at", "last_status_code", "last_error", "delivered_at"]),
+    ("outbox", &["id", "log_id", "num", "source", "tenant_id", "tipo", "reply", "template_id", "template_params", "status", "attempts", "next_attempt_at", "claimed_at", "provider_message_id", "provider_response", "last_error", "sent_at"]),
+];
+
+pub async fn run_migrations(client: &mut Object, migrations: &[Migration]) -> Result<usize, Box<dyn std::error::Error>> {
+    client.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name VARCHAR NOT NULL,
+            applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )"
+    ).await?;
+
+    let applied: Vec<i32> = client
+        .query("SELECT version FROM schema_migrations", &[])
+        .await?
+        .iter()
+        .map(|row| row.get("version"))
+        .collect();
+
+    let mut count = 0;
+    for migration in migrations.iter().filter(|m| !applied.contains(&m.version)) {
+        info!("Applying migration {:04} {}", migration.version, migration.name);
+        let transaction = client.transaction().await?;
+        if let Err(e) = transaction.batch_execute(migration.sql).await {
+            error!("Migration {:04} {} failed: {}", migration.version, migration.name, e);
+            return Err(Box::new(e));
+        }
+        transaction.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+            &[&migration.version, &migration.name]
+        ).await?;
+        transaction.commit().await?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Returns the `table.column` pairs from `expected` that do not exist in the database.
+pub async fn missing_columns(client: &Object, expected: &[(&str, &[&str])]) -> Result<Vec<String>, tokio_postgres::Error> {
+    let mut missing = Vec::new();
+    for (table, columns) in expected {
+        let existing: Vec<String> = client
+            .query(
+                "SELECT column_name::text FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1",
+                &[table]
+            )
+            .await?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        for column in columns.iter() {
+            if !existing.iter().any(|c| c == column) {
+                missing.push(format!("{}.{}", table, column));
+            }
+        }
+    }
+    Ok(missing)
+}
+
+pub async fn verify_schema(main_client: &Object, logs_client: &Object) -> Result<(), Box<dyn std::error::Error>> {
+    let mut missing = missing_columns(main_client, MAIN_SCHEMA).await?;
+    missing.extend(missing_columns(logs_client, LOGS_SCHEMA).await?);
+
+    let optional = missing_columns(main_client, OPTIONAL_MAIN_SCHEMA).await?;
+    if !optional.is_empty() {
+        warn!("Campaign attribution disabled, missing columns: {} (see migrations/main/template_messages.sql)", optional.join(", "));
+    }
+
+    if !missing.is_empty() {
+        error!("Database schema check failed, missing columns: {}", missing.join(", "));
+        return Err(format!("Missing database columns: {} (run the migrate command for the logs database)", missing.join(", ")).into());
+    }
+
+    info!("Database schema check passed");
+    Ok(())
+}
+
+/// Schema check for a tenant's own logs database, which gets the same migrations.
+pub async fn verify_logs_schema(logs_client: &Object) -> Result<(), Box<dyn std::error::Error>> {
+    let missing = missing_columns(logs_client, LOGS_SCHEMA).await?;
+    if !missing.is_empty() {
+        error!("Tenant logs database schema check failed, missing columns: {}", missing.join(", "));
+        return Err(format!("Missing database columns: {} (run the migrate command)", missing.join(", ")).into());
+    }
+    Ok(())
+}
@@ -1,3 +1,11 @@
+pub mod cache;
 pub mod connect;
 pub mod fetch;
-pub mod insert;
\ No newline at end of file
+pub mod follow_ups;
+pub mod insert;
+pub mod log_writer;
+pub mod migrate;
+pub mod outbox;
+pub mod partners;
+pub mod pools;
+pub mod scheduled;
\ No newline at end of file
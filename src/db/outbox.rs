@@ -0,0 +1,200 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Object;
+use log::{info, error};
+use tokio_postgres::{Error, Row};
+
+use super::insert::INSERT_LOG;
+use crate::api::api::OutboundMessage;
+use crate::process::process::Attribution;
+
+/// A claimed row of `outbox`.
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub num: String,
+    pub source: String,
+    pub tenant_id: Option<String>,
+    pub tipo: String,
+    pub message: OutboundMessage,
+    pub attempts: i32,
+}
+
+/// A reply and, unless its interaction was logged already, the `"button-answers"` row
+/// recorded with it.
+pub struct NewOutboxEntry<'a> {
+    pub num: &'a str,
+    pub source: &'a str,
+    pub tenant_id: Option<&'a str>,
+    pub tipo: &'a str,
+    pub message: &'a OutboundMessage,
+    pub log: Option<OutboxLog<'a>>,
+}
+
+pub struct OutboxLog<'a> {
+    pub log_message: &'a str,
+    pub button_text: &'a str,
+    pub attribution: &'a Attribution,
+}
+
+/// Rows left in `sending` this long were claimed by a process that died mid-send.
+const STALE_CLAIM: &str = "10 minutes";
+
+const RETURNING: &str = "RETURNING id, num, source, tenant_id, tipo, reply, template_id, template_params, attempts";
+
+fn entry_from_row(row: &Row) -> OutboxEntry {
+    let message = match (row.get::<_, Option<String>>("reply"), row.get::<_, Option<String>>("template_id")) {
+        (Some(reply), _) => OutboundMessage::Text(reply),
+        (None, id) => OutboundMessage::Template {
+            id: id.unwrap_or_default(),
+            params: row.get::<_, Option<Vec<String>>>("template_params").unwrap_or_default(),
+        },
+    };
+    OutboxEntry {
+        id: row.get("id"),
+        num: row.get("num"),
+        source: row.get("source"),
+        tenant_id: row.get("tenant_id"),
+        tipo: row.get("tipo"),
+        message,
+        attempts: row.get("attempts"),
+    }
+}
+
+/// Writes the log row (if any) and the reply in one transaction, so nothing is sent without a
+/// record.
+/// The row is returned already claimed (`sending`) for the caller to deliver right away.
+pub async fn insert_with_log(client: &mut Object, entry: &NewOutboxEntry<'_>) -> Result<OutboxEntry, Error> {
+    info!("Attempting to insert log and outbox entry for {} into the database:", entry.num);
+
+    let (reply, template_id, template_params) = match entry.message {
+        OutboundMessage::Text(body) => (Some(body.as_str()), None, None),
+        OutboundMessage::Template { id, params } => (None, Some(id.as_str()), Some(params.as_slice())),
+    };
+
+    let transaction = client.transaction().await?;
+    transaction.execute("SET LOCAL statement_timeout = '30s'", &[]).await?;
+    let log_id: Option<i32> = match &entry.log {
+        Some(log) => {
+            let attribution = log.attribution;
+            match transaction.query_one(
+                &format!("{} RETURNING id", INSERT_LOG),
+                &[&entry.num, &log.log_message, &log.button_text, &entry.tipo, &attribution.button_payload, &attribution.template_name, &attribution.campaign_id, &attribution.time_to_click_secs]
+            ).await {
+                Ok(row) => Some(row.get("id")),
+                Err(e) => {
+                    error!("Failed to execute INSERT query: {}", e);
+                    return Err(e);
+                }
+            }
+        }
+        None => None,
+    };
+    let row = match transaction.query_one(
+        &format!(
+            "INSERT INTO outbox (log_id, num, source, tenant_id, tipo, reply, template_id, template_params, status, attempts, claimed_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, 'sending', 1, now())
+             {}",
+            RETURNING
+        ),
+        &[&log_id, &entry.num, &entry.source, &entry.tenant_id, &entry.tipo, &reply, &template_id, &template_params]
+    ).await {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Failed to execute INSERT query: {}", e);
+            return Err(e);
+        }
+    };
+    transaction.commit().await?;
+
+    Ok(entry_from_row(&row))
+}
+
+/// Marks up to `limit` due rows as `sending` and returns them.
+pub async fn claim_due_outbox(client: &Object, limit: i64) -> Result<Vec<OutboxEntry>, Error> {
+    let rows = match client.query(
+        &format!(
+            "UPDATE outbox SET status = 'sending', claimed_at = now(), attempts = attempts + 1
+             WHERE id IN (
+                 SELECT id FROM outbox
+                 WHERE status = 'pending' AND next_attempt_at <= now()
+                 ORDER BY next_attempt_at
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )
+             {}",
+            RETURNING
+        ),
+        &[&limit]
+    ).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to execute UPDATE query: {}", e);
+            return Err(e);
+        }
+    };
+
+    Ok(rows.iter().map(entry_from_row).collect())
+}
+
+/// Settles rows stuck in `sending`. Whether the provider accepted them is unknown, so they
+/// become `unconfirmed` for someone to check, or go back to `pending` when `resend` is set
+/// (which risks a duplicate message). Returns the number of rows settled.
+pub async fn reconcile_stale(client: &Object, resend: bool) -> Result<u64, Error> {
+    let status = if resend { "pending" } else { "unconfirmed" };
+    match client.execute(
+        &format!(
+            "UPDATE outbox SET status = $1, next_attempt_at = now(), last_error = 'claim expired while sending'
+             WHERE status = 'sending' AND claimed_at < now() - INTERVAL '{}'",
+            STALE_CLAIM
+        ),
+        &[&status]
+    ).await {
+        Ok(count) => Ok(count),
+        Err(e) => {
+            error!("Failed to execute UPDATE query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+pub async fn mark_outbox_sent(client: &Object, id: i64, provider_message_id: Option<&str>, provider_response: &str) -> Result<(), Error> {
+    client.execute(
+        "UPDATE outbox SET status = 'sent', sent_at = now(), provider_message_id = $2, provider_response = $3, last_error = NULL WHERE id = $1",
+        &[&id, &provider_message_id, &provider_response]
+    ).await?;
+    Ok(())
+}
+
+/// Puts the row back for another attempt at `retry_at`, or gives up (`failed`) when `None`.
+pub async fn mark_outbox_failed(client: &Object, id: i64, error: &str, retry_at: Option<DateTime<Utc>>) -> Result<(), Error> {
+    match retry_at {
+        Some(retry_at) => client.execute(
+            "UPDATE outbox SET status = 'pending', next_attempt_at = $3, last_error = $2 WHERE id = $1",
+            &[&id, &error, &retry_at]
+        ).await?,
+        None => client.execute(
+            "UPDATE outbox SET status = 'failed', last_error = $2 WHERE id = $1",
+            &[&id, &error]
+        ).await?,
+    };
+    Ok(())
+}
+
+/// Gives up on a row that may no longer be sent, e.g. because the number opted out since it
+/// was written.
+pub async fn mark_outbox_cancelled(client: &Object, id: i64, reason: &str) -> Result<(), Error> {
+    client.execute(
+        "UPDATE outbox SET status = 'cancelled', last_error = $2 WHERE id = $1",
+        &[&id, &reason]
+    ).await?;
+    Ok(())
+}
+
+/// Puts a claimed row back until `until` without counting the claim as an attempt.
+pub async fn defer_outbox(client: &Object, id: i64, until: DateTime<Utc>) -> Result<(), Error> {
+    client.execute(
+        "UPDATE outbox SET status = 'pending', next_attempt_at = $2, attempts = attempts - 1 WHERE id = $1",
+        &[&id, &until]
+    ).await?;
+    Ok(())
+}
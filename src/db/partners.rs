@@ -0,0 +1,142 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Object;
+use log::{info, error};
+use tokio_postgres::Error;
+
+/// A claimed row of `partner_deliveries` with its endpoint.
+#[derive(Debug, Clone)]
+pub struct PartnerDelivery {
+    pub id: i64,
+    pub partner: String,
+    pub url: String,
+    pub secret: String,
+    pub payload: String,
+    pub attempts: i32,
+}
+
+/// A row of `partner_deliveries` for the delivery history.
+#[derive(Debug, Clone)]
+pub struct DeliveryHistory {
+    pub id: i64,
+    pub event: String,
+    pub status: String,
+    pub attempts: i32,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+/// How long a claimed delivery is hidden from other runs while it is posted.
+const CLAIM_FOR: &str = "5 minutes";
+
+/// Queues `payload` for every active partner endpoint of `source`. Returns how many were queued.
+pub async fn enqueue_partner_deliveries(client: &Object, source: &str, event: &str, payload: &str) -> Result<u64, Error> {
+    match client.execute(
+        "INSERT INTO partner_deliveries (webhook_id, event, payload)
+         SELECT id, $2, $3 FROM partner_webhooks WHERE active AND source = $1",
+        &[&source, &event, &payload]
+    ).await {
+        Ok(queued) => {
+            if queued > 0 {
+                info!("Queued {} {} delivery(ies) to partners of {}", queued, event, source);
+            }
+            Ok(queued)
+        }
+        Err(e) => {
+            error!("Failed to execute INSERT query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+pub async fn claim_due_partner_deliveries(client: &Object, limit: i64) -> Result<Vec<PartnerDelivery>, Error> {
+    let rows = match client.query(
+        &format!(
+            "UPDATE partner_deliveries d SET next_attempt_at = now() + INTERVAL '{}', attempts = d.attempts + 1
+             FROM partner_webhooks w
+             WHERE w.id = d.webhook_id
+               AND d.id IN (
+                   SELECT id FROM partner_deliveries
+                   WHERE status = 'pending' AND next_attempt_at <= now()
+                   ORDER BY next_attempt_at
+                   LIMIT $1
+                   FOR UPDATE SKIP LOCKED
+               )
+             RETURNING d.id, w.partner, w.url, w.secret, d.payload, d.attempts",
+            CLAIM_FOR
+        ),
+        &[&limit]
+    ).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to execute UPDATE query: {}", e);
+            return Err(e);
+        }
+    };
+
+    Ok(rows
+        .iter()
+        .map(|row| PartnerDelivery {
+            id: row.get("id"),
+            partner: row.get("partner"),
+            url: row.get("url"),
+            secret: row.get("secret"),
+            payload: row.get("payload"),
+            attempts: row.get("attempts"),
+        })
+        .collect())
+}
+
+pub async fn mark_delivered(client: &Object, id: i64, status_code: i32) -> Result<(), Error> {
+    client.execute(
+        "UPDATE partner_deliveries SET status = 'delivered', delivered_at = now(), last_status_code = $2, last_error = NULL WHERE id = $1",
+        &[&id, &status_code]
+    ).await?;
+    Ok(())
+}
+
+/// Records a failed attempt: retried at `retry_at`, or given up (`failed`) when `None`.
+pub async fn mark_attempt_failed(
+    client: &Object,
+    id: i64,
+    status_code: Option<i32>,
+    error: &str,
+    retry_at: Option<DateTime<Utc>>
+) -> Result<(), Error> {
+    client.execute(
+        "UPDATE partner_deliveries
+         SET status = CASE WHEN $4::TIMESTAMPTZ IS NULL THEN 'failed' ELSE 'pending' END,
+             next_attempt_at = COALESCE($4, next_attempt_at),
+             last_status_code = $2,
+             last_error = $3
+         WHERE id = $1",
+        &[&id, &status_code, &error, &retry_at]
+    ).await?;
+    Ok(())
+}
+
+pub async fn fetch_delivery_history(client: &Object, partner: &str, limit: i64) -> Result<Vec<DeliveryHistory>, Error> {
+    let rows = client.query(
+        "SELECT d.id, d.event, d.status, d.attempts, d.last_status_code, d.last_error, d.created_at, d.delivered_at
+         FROM partner_deliveries d JOIN partner_webhooks w ON w.id = d.webhook_id
+         WHERE w.partner = $1
+         ORDER BY d.created_at DESC
+         LIMIT $2",
+        &[&partner, &limit]
+    ).await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| DeliveryHistory {
+            id: row.get("id"),
+            event: row.get("event"),
+            status: row.get("status"),
+            attempts: row.get("attempts"),
+            last_status_code: row.get("last_status_code"),
+            last_error: row.get("last_error"),
+            created_at: row.get("created_at"),
+            delivered_at: row.get("delivered_at"),
+        })
+        .collect())
+}
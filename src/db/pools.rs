@@ -0,0 +1,34 @@
+use deadpool_postgres::{Object, Pool};
+use log::info;
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+use super::connect::create_pool;
+
+/// Connection pools keyed by database URL, created on first use and kept for the life of
+/// the process, so tenants with their own logs database share one pool per URL.
+#[derive(Default)]
+pub struct Pools {
+    pools: Mutex<HashMap<String, Pool>>,
+}
+
+impl Pools {
+    /// The lock is only held to look the pool up or store it; creating a pool connects to the
+    /// database, and one unreachable database must not stall clicks for every other one.
+    pub async fn client(&self, url: &str) -> Result<Object, Box<dyn std::error::Error>> {
+        let existing = self.pools.lock().await.get(url).cloned();
+        let pool = match existing {
+            Some(pool) => pool,
+            None => {
+                let created = create_pool(url).await?;
+                let mut pools = self.pools.lock().await;
+                // Another task may have created one meanwhile; keep the first.
+                if !pools.contains_key(url) {
+                    info!("Created database pool #{}", pools.len() + 1);
+                }
+                pools.entry(url.to_string()).or_insert(created).clone()
+            }
+        };
+        Ok(pool.get().await?)
+    }
+}
@@ -0,0 +1,123 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Object;
+use log::{info, error};
+use tokio_postgres::Error;
+
+/// A claimed row of `scheduled_sends`.
+#[derive(Debug, Clone)]
+pub struct ScheduledSend {
+    pub id: i64,
+    pub num: String,
+    pub source: String,
+    pub tenant_id: Option<String>,
+    pub tipo: String,
+    pub reply: Option<String>,
+    pub template_id: Option<String>,
+    pub template_params: Vec<String>,
+    pub attempts: i32,
+}
+
+/// Rows left in `sending` this long (the process died mid-send) are claimed again.
+const STALE_CLAIM: &str = "10 minutes";
+
+/// A reply to hold back until `send_after`. The template, when the route has one, is used
+/// if the 24h window has closed by then.
+pub struct NewScheduledSend<'a> {
+    pub num: &'a str,
+    pub source: &'a str,
+    pub tenant_id: Option<&'a str>,
+    pub tipo: &'a str,
+    pub reply: Option<&'a str>,
+    pub template: Option<(&'a str, &'a [String])>,
+    pub send_after: DateTime<Utc>,
+}
+
+pub async fn insert_scheduled_send(client: &Object, send: &NewScheduledSend<'_>) -> Result<i64, Error> {
+    info!("Scheduling reply to {} for {}", send.num, send.send_after);
+
+    let (template_id, template_params) = send.template.unzip();
+    match client.query_one(
+        "INSERT INTO scheduled_sends (num, source, tenant_id, tipo, reply, template_id, template_params, send_after) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+        &[&send.num, &send.source, &send.tenant_id, &send.tipo, &send.reply, &template_id, &template_params, &send.send_after]
+    ).await {
+        Ok(row) => Ok(row.get("id")),
+        Err(e) => {
+            error!("Failed to execute INSERT query: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Marks up to `limit` due rows as `sending` and returns them. `SKIP LOCKED` lets several
+/// consumers run the scheduler without sending the same row twice.
+pub async fn claim_due(client: &Object, limit: i64) -> Result<Vec<ScheduledSend>, Error> {
+    let rows = match client.query(
+        &format!(
+            "UPDATE scheduled_sends SET status = 'sending', claimed_at = now(), attempts = attempts + 1
+             WHERE id IN (
+                 SELECT id FROM scheduled_sends
+                 WHERE send_after <= now()
+                   AND (status = 'pending' OR (status = 'sending' AND claimed_at < now() - INTERVAL '{}'))
+                 ORDER BY send_after
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, num, source, tenant_id, tipo, reply, template_id, template_params, attempts",
+            STALE_CLAIM
+        ),
+        &[&limit]
+    ).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to execute UPDATE query: {}", e);
+            return Err(e);
+        }
+    };
+
+    Ok(rows
+        .iter()
+        .map(|row| ScheduledSend {
+            id: row.get("id"),
+            num: row.get("num"),
+            source: row.get("source"),
+            tenant_id: row.get("tenant_id"),
+            tipo: row.get("tipo"),
+            reply: row.get("reply"),
+            template_id: row.get("template_id"),
+            template_params: row.get::<_, Option<Vec<String>>>("template_params").unwrap_or_default(),
+            attempts: row.get("attempts"),
+        })
+        .collect())
+}
+
+pub async fn mark_sent(client: &Object, id: i64, provider_response: Option<&str>) -> Result<(), Error> {
+    client.execute(
+        "UPDATE scheduled_sends SET status = 'sent', sent_at = now(), provider_response = $2 WHERE id = $1",
+        &[&id, &provider_response]
+    ).await?;
+    Ok(())
+}
+
+/// Puts the row back for another attempt at `retry_at`, or gives up (`failed`) when `None`.
+pub async fn mark_failed(client: &Object, id: i64, error: &str, retry_at: Option<DateTime<Utc>>) -> Result<(), Error> {
+    match retry_at {
+        Some(retry_at) => client.execute(
+            "UPDATE scheduled_sends SET status = 'pending', send_after = $3, last_error = $2 WHERE id = $1",
+            &[&id, &error, &retry_at]
+        ).await?,
+        None => client.execute(
+            "UPDATE scheduled_sends SET status = 'failed', last_error = $2 WHERE id = $1",
+            &[&id, &error]
+        ).await?,
+    };
+    Ok(())
+}
+
+/// Drops a row without sending it, e.g. because the number opted out in the meantime.
+pub async fn mark_cancelled(client: &Object, id: i64, reason: &str) -> Result<(), Error> {
+    client.execute(
+        "UPDATE scheduled_sends SET status = 'cancelled', last_error = $2 WHERE id = $1",
+        &[&id, &reason]
+    ).await?;
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    routing::get,
+    Router,
+};
+use bytes::Bytes;
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::BasicProperties;
+use log::{info, error, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::process::process::Provider;
+use crate::process::state::AppState;
+use crate::rabbit::publish::Publisher;
+use crate::signature::signature::{sign, token_matches, verify, PROVIDER_SIGNATURE_HEADER, SIGNATURE_HEADER};
+
+struct ServerState {
+    app: Arc<AppState>,
+    publisher: Publisher,
+    queue: String,
+}
+
+/// Runs the webhook ingestion endpoint: `GET /webhook` answers Meta's subscription challenge
+/// and `POST /webhook` validates a webhook and publishes it to the consumer queue.
+pub async fn serve(app: Arc<AppState>, addr: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let queue = app.env_vars.http_publish_queue.clone();
+    let state = Arc::new(ServerState {
+        publisher: Publisher::new(&app.env_vars.rabbit_url),
+        app,
+        queue,
+    });
+
+    let app = Router::new()
+        .route("/webhook", get(verify_subscription).post(receive_webhook))
+        .route("/health", get(|| async { "ok" }))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    info!("Webhook receiver listening on {}", addr);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn verify_subscription(
+    State(state): State<Arc<ServerState>>,
+    Query(params): Query<HashMap<String, String>>
+) -> (StatusCode, String) {
+    let mode = params.get("hub.mode").map(String::as_str);
+    let token = params.get("hub.verify_token").map(String::as_str);
+    let challenge = params.get("hub.challenge");
+
+    match (mode, token, challenge, state.app.env_vars.meta_verify_token.as_deref()) {
+        (Some("subscribe"), Some(token), Some(challenge), Some(expected)) if token_matches(expected, token) => {
+            info!("Webhook subscription verified");
+            (StatusCode::OK, challenge.clone())
+        }
+        _ => {
+            warn!("Rejected webhook subscription verification");
+            (StatusCode::FORBIDDEN, "Forbidden".to_string())
+        }
+    }
+}
+
+/// Header (or `token` query parameter) carrying `GUPSHUP_WEBHOOK_TOKEN` on Gupshup callbacks,
+/// which are not signed.
+const GUPSHUP_TOKEN_HEADER: &str = "X-Webhook-Token";
+
+/// Checks the webhook the way its provider authenticates: Meta signs the body with the app
+/// secret, Gupshup callbacks carry a shared token configured in their callback URL or headers.
+/// `Ok(false)` means the provider's check is not configured.
+fn authenticate(
+    env_vars: &crate::config::config::EnvVars,
+    provider: Provider,
+    headers: &HeaderMap,
+    params: &HashMap<String, String>,
+    body: &[u8]
+) -> Result<bool, &'static str> {
+    match provider {
+        Provider::Meta => {
+            let Some(secret) = &env_vars.meta_app_secret else {
+                return Ok(false);
+            };
+            let signature = headers
+                .get("X-Hub-Signature-256")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            if verify(secret, body, signature) { Ok(true) } else { Err("invalid X-Hub-Signature-256") }
+        }
+        Provider::Gupshup | Provider::GupshupV2 => {
+            let Some(expected) = &env_vars.gupshup_webhook_token else {
+                return Ok(false);
+            };
+            let given = headers
+                .get(GUPSHUP_TOKEN_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .or(params.get("token").map(String::as_str))
+                .unwrap_or_default();
+            if token_matches(expected, given) { Ok(true) } else { Err("invalid Gupshup webhook token") }
+        }
+    }
+}
+
+async fn receive_webhook(
+    State(state): State<Arc<ServerState>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes
+) -> StatusCode {
+    let provider = match crate::process::process::parse_webhook_data(&body) {
+        Ok(Some(click)) => click.provider,
+        Ok(None) => {
+            info!("Webhook has no button click, not publishing");
+            return StatusCode::OK;
+        }
+        Err(e) => {
+            warn!("Rejected malformed webhook: {}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    let env_vars = &state.app.env_vars;
+    let verified = match authenticate(env_vars, provider, &headers, &params, &body) {
+        Ok(verified) => verified,
+        Err(reason) => {
+            warn!("Rejected {:?} webhook: {}", provider, reason);
+            return StatusCode::UNAUTHORIZED;
+        }
+    };
+    if !verified {
+        if env_vars.amqp_signing_secret.is_some() {
+            warn!("Rejected {:?} webhook: no authentication configured for this provider", provider);
+            return StatusCode::UNAUTHORIZED;
+        }
+        warn!("Accepting unauthenticated {:?} webhook (no authentication configured for this provider)", provider);
+    }
+
+    // Only a webhook whose origin was checked is vouched for; signing anything else would let
+    // the public endpoint sign arbitrary bodies for the consumer.
+    let mut message_headers = FieldTable::default();
+    if verified && let Some(secret) = &state.app.env_vars.amqp_signing_secret {
+        message_headers.insert(SIGNATURE_HEADER.into(), AMQPValue::LongString(sign(secret, &body).into()));
+    }
+    if provider == Provider::Meta && let Some(provider_signature) = headers.get("X-Hub-Signature-256").and_then(|value| value.to_str().ok()) {
+        message_headers.insert(PROVIDER_SIGNATURE_HEADER.into(), AMQPValue::LongString(provider_signature.into()));
+    }
+
+    let properties = BasicProperties::default()
+        .with_content_type("application/json".into())
+        .with_delivery_mode(2)
+        .with_headers(message_headers);
+
+    match state.publisher.publish("", &state.queue, &body, properties).await {
+        Ok(_) => {
+            info!("Webhook published to {}", state.queue);
+            StatusCode::OK
+        }
+        Err(e) => {
+            error!("Failed to publish webhook to {}: {}", state.queue, e);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
@@ -1,18 +1,35 @@
+#![allow(clippy::module_inception)]
+
 mod config;
 mod rabbit;
 mod process;
 mod db;
 mod api;
+mod cli;
+mod crypto;
+mod http;
+mod signature;
+mod spool;
 use env_logger::{Builder, Env};
 use log::{error, info, warn};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::Arc;
 use rabbit::{connect as rmq_connect};
 use tokio::select;
 use tokio::signal;
+use tokio::sync::watch;
 use futures::pin_mut;
 use futures::StreamExt;
+use std::time::Duration;
 use tokio::time::sleep;
 
+/// How long a delivery is held before it is requeued because a database is down.
+const REQUEUE_DELAY: Duration = Duration::from_secs(5);
+/// Requeues per delivery before its click is spooled unanswered and acked, so an outage
+/// holds each message for about a minute instead of blocking the queue.
+const MAX_REQUEUES: u32 = 12;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let env = Env::default().filter_or("RUST_LOG", "debug");
@@ -27,19 +44,70 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("Starting button consumer application");
     info!("Log level is set - if you see this message, logging is working!");
 
-    let env_vars = config::config::load();
+    let state = Arc::new(process::state::AppState::new(config::config::load()));
+    let env_vars = &state.env_vars;
+
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("replay") => return cli::replay::run(&state, &args[2..]).await,
+        Some("migrate") => return cli::migrate::run(&state).await,
+        Some("import-nmp") => return cli::nmp::run(&state, &args[2..]).await,
+        Some("partner-deliveries") => return cli::partners::run(&state, &args[2..]).await,
+        Some("encrypt-secret") => return cli::credentials::run(env_vars, &args[2..]),
+        Some(command) => return Err(format!("Unknown command: {}", command).into()),
+        None => {}
+    }
+
+    check_schema(&state).await?;
+
+    for db_url in [env_vars.db_url.clone(), env_vars.db_url_logs.clone()] {
+        let listener_state = state.clone();
+        tokio::spawn(async move {
+            db::cache::listen_for_changes(db_url, || listener_state.invalidate_caches()).await;
+        });
+    }
+
+    if !env_vars.shadow_mode {
+        tokio::spawn(process::scheduler::run(state.clone()));
+        tokio::spawn(spool::spool::run(state.clone()));
+    }
+
+    if let Some(addr) = env_vars.http_listen_addr.clone() {
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = http::server::serve(server_state, addr).await {
+                error!("Webhook receiver stopped: {}", e);
+            }
+        });
+    }
 
+    let mut backoff = rmq_connect::Backoff::new(
+        Duration::from_secs(env_vars.rabbit_backoff_initial_secs),
+        Duration::from_secs(env_vars.rabbit_backoff_max_secs),
+    );
+
+    let shutdown = watch_shutdown();
     loop {
-        match run_consumer(&env_vars.rabbit_url, &env_vars.db_url, &env_vars.api_key_gup, &env_vars.api_key_huggy, &env_vars.api_key_huggy2, &env_vars.db_url_logs).await {
+        match run_consumer(&state, &mut backoff, shutdown.clone()).await {
             Ok(_) => {
                 info!("Application shutdown requested");
+                state.log_writer.flush().await;
                 break;
             }
             Err(e) => {
                 error!("Error in consumer loop: {}", e);
                 println!("ERROR: Consumer loop failed: {}", e);
-                info!("Reconnecting in 5 seconds...");
-                sleep(std::time::Duration::from_secs(5)).await;
+                let reconnects = rmq_connect::record_reconnect();
+                let delay = backoff.next_delay();
+                warn!("Reconnecting to RabbitMQ in {:?} (reconnect #{})", delay, reconnects);
+                select! {
+                    _ = sleep(delay) => {},
+                    _ = shutdown_requested(shutdown.clone()) => {
+                        info!("Application shutdown requested");
+                        state.log_writer.flush().await;
+                        break;
+                    }
+                }
             }
         }
     }
@@ -47,86 +115,111 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+async fn check_schema(state: &process::state::AppState) -> Result<(), Box<dyn std::error::Error>> {
+    let db_client = state.pools.client(&state.env_vars.db_url).await?;
+    let db_logs = state.pools.client(&state.env_vars.db_url_logs).await?;
+    db::migrate::verify_schema(&db_client, &db_logs).await?;
+
+    if state.env_vars.multi_tenant {
+        for (tenant, url) in state.tenants.logs_db_urls(&db_logs).await? {
+            info!("Checking logs database schema for tenant {}", tenant);
+            let tenant_logs = state.pools.client(&url).await?;
+            db::migrate::verify_logs_schema(&tenant_logs).await?;
+        }
+    }
+    Ok(())
+}
+
 async fn run_consumer(
-    rabbit_url: &str,
-    db_url: &str,
-    api_key_gup: &str,
-    api_key_hug: &str,
-    api_key_hug2: &str,
-    db_url_logs: &str
+    state: &Arc<process::state::AppState>,
+    backoff: &mut rmq_connect::Backoff,
+    shutdown: watch::Receiver<bool>
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let (mut consumer, _) = match rmq_connect::create_rabbitmq_consumer(rabbit_url).await {
-        Some((consumer, connection)) => (consumer, connection),
-        None => return Err("Failed to create RabbitMQ consumer".into()),
+    let env_vars = &state.env_vars;
+    let (queues, mode) = if env_vars.shadow_mode {
+        let recorder = process::shadow::Recorder::from_output(env_vars.shadow_output.as_deref());
+        warn!("Shadow mode enabled: consuming from {} and recording to {:?}, nothing will be sent", env_vars.shadow_queue, recorder);
+        (vec![env_vars.shadow_queue.clone()], process::process::Mode::Shadow(recorder))
+    } else {
+        (env_vars.rabbit_queues.clone(), process::process::Mode::Live)
+    };
+
+    let Some(rmq_connect::RabbitSession { connection, mut consumer, mut errors }) =
+        rmq_connect::create_rabbitmq_consumer(&env_vars.rabbit_url, &queues, backoff, shutdown_requested(shutdown.clone())).await
+    else {
+        return Ok(());
     };
 
-    info!("Consumer ready, waiting for webhooks...");
+    if env_vars.amqp_signing_secret.is_some() {
+        rmq_connect::declare_queue(&connection, &env_vars.quarantine_queue).await?;
+    }
+    let publisher = rabbit::publish::Publisher::new(&env_vars.rabbit_url);
+
+    info!("Consumer ready, waiting for webhooks... (reconnects so far: {})", rmq_connect::reconnect_count());
     info!("Press Ctrl+C to exit");
 
-    loop {
-        let ctrl_c_future = signal::ctrl_c();
-        pin_mut!(ctrl_c_future);
+    let shutdown = shutdown_requested(shutdown);
+    pin_mut!(shutdown);
+    // Requeue counts by message body; a requeued delivery comes back with a new tag.
+    let mut requeues: HashMap<u64, u32> = HashMap::new();
 
+    loop {
         select! {
             delivery_result = consumer.next() => {
                 match delivery_result {
                     Some(Ok(delivery)) => {
+                        if let Err(reason) = signature::signature::verify_delivery(env_vars, &delivery.data, delivery.properties.headers().as_ref()) {
+                            quarantine_delivery(&publisher, &env_vars.quarantine_queue, &delivery, reason).await;
+                            continue;
+                        }
+
                         let data = delivery.data.clone();
-                        let db_url = db_url.to_string();
-                        let db_url_logs = db_url_logs.to_string();
-                        let api_key_hug = api_key_hug.to_string();
-                        let api_key_gup = api_key_gup.to_string();
-                        let api_key_hug2 = api_key_hug2.to_string();
+                        let task_state = state.clone();
+                        let task_mode = mode.clone();
 
                         let handle = tokio::spawn(async move {
                             info!("Starting webhook processing in spawned task");
-                            
-                            let db_pool = match db::connect::create_pool(&db_url).await {
-                                Ok(pool) => pool,
-                                Err(e) => {
-                                    error!("Failed to create database pool: {}", e);
-                                    return;
-                                }
-                            };
-                            
-                            let db_logs_pool = match db::connect::create_pool(&db_url_logs).await {
-                                Ok(pool) => pool,
-                                Err(e) => {
-                                    error!("Failed to create logs database pool: {}", e);
-                                    return;
-                                }
-                            };
-                            
-                            let db_client = match db_pool.get().await {
-                                Ok(client) => Arc::new(client),
-                                Err(e) => {
-                                    error!("Failed to get database client: {}", e);
-                                    return;
-                                }
-                            };
-                            
-                            let db_logs = match db_logs_pool.get().await {
-                                Ok(client) => Arc::new(client),
-                                Err(e) => {
-                                    error!("Failed to get logs database client: {}", e);
-                                    return;
-                                }
-                            };
-                            
-                            match process::process::process_webhook(&data, &db_client, &api_key_hug, &api_key_gup, &api_key_hug2, &db_logs).await {
+
+                            match process::process::process_webhook(&data, &task_state, &task_mode).await {
                                 Ok(_) => {
                                     info!("Successfully processed webhook in spawned task");
+                                    false
+                                },
+                                Err(e) if e.is::<process::process::Unavailable>() => {
+                                    warn!("Cannot process webhook now, it will be requeued: {}", e);
+                                    true
                                 },
                                 Err(e) => {
                                     error!("Error processing webhook in spawned task: {}", e);
+                                    false
                                 }
                             }
                         });
 
-                        if let Err(e) = handle.await {
-                            error!("Spawned task failed: {}", e);
-                        }
+                        let requeue = match handle.await {
+                            Ok(requeue) => requeue,
+                            Err(e) => {
+                                error!("Spawned task failed: {}", e);
+                                false
+                            }
+                        };
 
+                        let key = message_key(&delivery.data);
+                        let requeue = requeue && {
+                            let count = requeues.entry(key).or_insert(0);
+                            *count += 1;
+                            *count <= MAX_REQUEUES || !give_up(state, &mode, &delivery.data, *count).await
+                        };
+                        if requeue {
+                            // A requeued message comes straight back, so wait before handing it back.
+                            sleep(REQUEUE_DELAY).await;
+                            let options = lapin::options::BasicNackOptions { requeue: true, ..Default::default() };
+                            if let Err(e) = delivery.nack(options).await {
+                                error!("Failed to requeue message: {}", e);
+                            }
+                            continue;
+                        }
+                        requeues.remove(&key);
                         if let Err(e) = delivery.ack(lapin::options::BasicAckOptions::default()).await {
                             error!("Failed to acknowledge message: {}", e);
                         }
@@ -142,12 +235,106 @@ async fn run_consumer(
                 }
             },
 
-            _ = ctrl_c_future => {
+            Some(e) = errors.recv() => {
+                error!("RabbitMQ connection lost: {}", e);
+                return Err(Box::new(e));
+            },
+
+            _ = &mut shutdown => {
                 info!("Received shutdown signal");
+                if let Err(e) = connection.close(200, "Consumer shutting down").await {
+                    warn!("Failed to close RabbitMQ connection cleanly: {}", e);
+                }
                 break;
             }
         }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+fn message_key(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Spools the click of a delivery requeued `MAX_REQUEUES` times so it can be acked. Returns
+/// false if the spool refuses it too, in which case it keeps being requeued. Shadow mode
+/// records nothing locally, so there the delivery is dropped.
+async fn give_up(state: &process::state::AppState, mode: &process::process::Mode, data: &[u8], requeues: u32) -> bool {
+    if !matches!(mode, process::process::Mode::Live) {
+        warn!("Database still unavailable after {} requeues, dropping shadow delivery", requeues - 1);
+        return true;
+    }
+    match process::process::spool_unprocessed(data, state).await.map_err(|e| e.to_string()) {
+        Ok(()) => {
+            warn!("Database still unavailable after {} requeues, click spooled without a reply", requeues - 1);
+            true
+        }
+        Err(e) => {
+            error!("Could not spool the click of a delivery requeued {} times: {}", requeues - 1, e);
+            false
+        }
+    }
+}
+
+/// Installs the Ctrl+C and SIGTERM (what `docker stop` and Kubernetes send) handlers right
+/// away, so a signal that arrives while reconnecting is not lost. The receiver turns `true`
+/// once either arrives.
+fn watch_shutdown() -> watch::Receiver<bool> {
+    let (sender, receiver) = watch::channel(false);
+    let interrupt = signal::unix::signal(signal::unix::SignalKind::interrupt());
+    let terminate = signal::unix::signal(signal::unix::SignalKind::terminate());
+    tokio::spawn(async move {
+        match (interrupt, terminate) {
+            (Ok(mut interrupt), Ok(mut terminate)) => {
+                select! {
+                    _ = interrupt.recv() => {},
+                    _ = terminate.recv() => {},
+                }
+            }
+            (_, Err(e)) | (Err(e), _) => {
+                warn!("Cannot listen for SIGTERM, only Ctrl+C will shut down cleanly: {}", e);
+                let _ = signal::ctrl_c().await;
+            }
+        }
+        let _ = sender.send(true);
+    });
+    receiver
+}
+
+/// Resolves once shutdown was requested, including before the call.
+async fn shutdown_requested(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|requested| *requested).await;
+}
+
+async fn quarantine_delivery(
+    publisher: &rabbit::publish::Publisher,
+    queue: &str,
+    delivery: &lapin::message::Delivery,
+    reason: &str
+) {
+    let quarantined = signature::signature::record_quarantine();
+    warn!("Rejected unauthenticated message ({}), moving it to {} (quarantined so far: {})", reason, queue, quarantined);
+
+    let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+    headers.insert("x-quarantine-reason".into(), lapin::types::AMQPValue::LongString(reason.into()));
+    let properties = lapin::BasicProperties::default()
+        .with_delivery_mode(2)
+        .with_headers(headers);
+
+    match publisher.publish("", queue, &delivery.data, properties).await {
+        Ok(_) => {
+            if let Err(e) = delivery.ack(lapin::options::BasicAckOptions::default()).await {
+                error!("Failed to acknowledge quarantined message: {}", e);
+            }
+        }
+        Err(e) => {
+            error!("Failed to quarantine message, rejecting it: {}", e);
+            if let Err(e) = delivery.reject(lapin::options::BasicRejectOptions::default()).await {
+                error!("Failed to reject message: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+use super::process::ButtonClick;
+
+/// Everything we know about one delivery, written to the logs database whether or not it succeeded.
+#[derive(Debug, Default)]
+pub struct AuditRecord {
+    pub payload: Vec<u8>,
+    pub click: Option<ButtonClick>,
+    pub tenant_id: Option<String>,
+    pub lag_secs: Option<i64>,
+    pub tipo: Option<String>,
+    pub provider_response: Option<String>,
+    pub duration_ms: i64,
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(payload: &[u8]) -> Self {
+        AuditRecord {
+            payload: payload.to_vec(),
+            ..AuditRecord::default()
+        }
+    }
+}
+
+pub fn compress_payload(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
@@ -0,0 +1,12 @@
+use chrono::{DateTime, FixedOffset, Utc};
+
+/// Brazil has had no daylight saving time since 2019, so America/Sao_Paulo is a fixed UTC-3.
+const SAO_PAULO_OFFSET_SECS: i32 = -3 * 3600;
+
+pub fn sao_paulo_offset() -> FixedOffset {
+    FixedOffset::east_opt(SAO_PAULO_OFFSET_SECS).expect("UTC-3 is a valid offset")
+}
+
+pub fn now_sao_paulo() -> DateTime<FixedOffset> {
+    Utc::now().with_timezone(&sao_paulo_offset())
+}
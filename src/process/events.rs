@@ -0,0 +1,78 @@
+use lapin::BasicProperties;
+use log::{info, error};
+use serde::Serialize;
+
+use super::audit::AuditRecord;
+use crate::api::api::provider_message_id;
+use super::process::Provider;
+use super::state::AppState;
+
+pub const CLICK_PROCESSED: &str = "button_click.processed";
+
+/// Published after a click is processed, for downstream consumers (CRM, BI).
+#[derive(Debug, Serialize)]
+pub struct ClickProcessedEvent<'a> {
+    pub event: &'static str,
+    pub message_id: &'a str,
+    pub num: &'a str,
+    pub source: &'a str,
+    pub tenant_id: Option<&'a str>,
+    pub provider: Provider,
+    pub button_text: &'a str,
+    pub button_payload: &'a str,
+    pub tipo: &'a str,
+    pub reply_message_id: Option<String>,
+    pub clicked_at: Option<String>,
+    pub processed_at: String,
+    pub processing_lag_secs: Option<i64>,
+}
+
+/// The event for a processed click, or `None` when the delivery had no routed click.
+pub fn processed_event(audit: &AuditRecord) -> Option<ClickProcessedEvent<'_>> {
+    let (Some(click), Some(tipo)) = (&audit.click, &audit.tipo) else {
+        return None;
+    };
+
+    Some(ClickProcessedEvent {
+        event: CLICK_PROCESSED,
+        message_id: &click.message_id,
+        num: &click.from,
+        source: &click.source,
+        tenant_id: audit.tenant_id.as_deref(),
+        provider: click.provider,
+        button_text: &click.button_text,
+        button_payload: &click.button_payload,
+        tipo,
+        reply_message_id: audit.provider_response.as_deref().and_then(provider_message_id),
+        clicked_at: click.clicked_at().map(|at| at.to_rfc3339()),
+        processed_at: chrono::Utc::now().to_rfc3339(),
+        processing_lag_secs: audit.lag_secs,
+    })
+}
+
+/// Publishes `button_click.processed` to `EVENTS_EXCHANGE` with publisher confirms. Failures
+/// are logged only: the customer has already been answered, so the delivery is not retried.
+pub async fn publish_processed(state: &AppState, event: &ClickProcessedEvent<'_>) {
+    let (Some(publisher), Some(exchange)) = (&state.events, &state.env_vars.events_exchange) else {
+        return;
+    };
+
+    let body = match serde_json::to_vec(event) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to serialize {} event: {}", CLICK_PROCESSED, e);
+            return;
+        }
+    };
+
+    let properties = BasicProperties::default()
+        .with_content_type("application/json".into())
+        .with_delivery_mode(2)
+        .with_type(CLICK_PROCESSED.into())
+        .with_message_id(event.message_id.into());
+
+    match publisher.publish(exchange, &state.env_vars.events_routing_key, &body, properties).await {
+        Ok(_) => info!("Published {} for message {} to {}", CLICK_PROCESSED, event.message_id, exchange),
+        Err(e) => error!("Failed to publish {} for message {}: {}", CLICK_PROCESSED, event.message_id, e),
+    }
+}
@@ -0,0 +1,152 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use deadpool_postgres::Object;
+use log::{info, error, warn};
+
+use super::outbox;
+use super::process::Attribution;
+use super::{nmp, quiet_hours, service_window};
+use super::state::AppState;
+use crate::api::api::OutboundMessage;
+use crate::config::config::EnvVars;
+use crate::db::follow_ups::{
+    claim_due_follow_ups, close_follow_up, insert_follow_up, record_reminder, reschedule_follow_up, FollowUp
+};
+use crate::db::outbox::{NewOutboxEntry, OutboxLog};
+
+const REMINDER_REPLY: &str = "Oi! Ainda está por aí? 😊\n\nPara continuarmos, digite:\n1️⃣ Para sim\n2️⃣ Para não";
+const REMINDER_LOG: &str = "LEMBRETE ENVIADO";
+
+/// Follow-ups claimed per run.
+const BATCH_SIZE: i64 = 50;
+const RETRY_DELAY_MINUTES: i64 = 5;
+const MAX_ATTEMPTS: i32 = 5;
+
+/// Starts reminding `num` about a question just sent, when the route is one of
+/// `FOLLOW_UP_TIPOS` and reminders are enabled.
+pub async fn start(
+    db_client_control: &Object,
+    env_vars: &EnvVars,
+    num: &str,
+    source: &str,
+    tenant_id: Option<&str>,
+    tipo: &str
+) -> Result<(), tokio_postgres::Error> {
+    if env_vars.follow_up_max_reminders <= 0 || !env_vars.follow_up_tipos.iter().any(|t| t == tipo) {
+        return Ok(());
+    }
+    let next_at = Utc::now() + ChronoDuration::minutes(env_vars.follow_up_delay_mins);
+    insert_follow_up(db_client_control, num, source, tenant_id, tipo, next_at).await
+}
+
+/// Sends the reminders that are due. Called from the scheduler loop.
+pub async fn deliver_due(state: &AppState, db_client: &Object, db_client_control: &Object) -> Result<(), tokio_postgres::Error> {
+    let due = claim_due_follow_ups(db_client_control, BATCH_SIZE).await?;
+    if !due.is_empty() {
+        info!("Sending {} follow-up reminder(s)", due.len());
+    }
+    for follow_up in due {
+        remind(state, db_client, db_client_control, &follow_up).await;
+    }
+    Ok(())
+}
+
+async fn remind(state: &AppState, db_client: &Object, db_client_control: &Object, follow_up: &FollowUp) {
+    let result = send_reminder(state, db_client, db_client_control, follow_up)
+        .await
+        .map_err(|e| e.to_string());
+
+    let updated = match &result {
+        Ok(()) => return,
+        Err(e) if follow_up.attempts + 1 < MAX_ATTEMPTS => {
+            warn!("Follow-up #{} to {} failed (attempt {}), retrying in {} minutes: {}", follow_up.id, follow_up.num, follow_up.attempts + 1, RETRY_DELAY_MINUTES, e);
+            let retry_at = Utc::now() + ChronoDuration::minutes(RETRY_DELAY_MINUTES);
+            reschedule_follow_up(db_client_control, follow_up.id, retry_at, Some(e)).await
+        }
+        Err(e) => {
+            error!("Follow-up #{} to {} failed after {} attempts: {}", follow_up.id, follow_up.num, MAX_ATTEMPTS, e);
+            close_follow_up(db_client_control, follow_up.id, "failed", e).await
+        }
+    };
+    if let Err(e) = updated {
+        error!("Failed to update follow-up #{}: {}", follow_up.id, e);
+    }
+}
+
+async fn send_reminder(
+    state: &AppState,
+    db_client: &Object,
+    db_client_control: &Object,
+    follow_up: &FollowUp
+) -> Result<(), Box<dyn std::error::Error>> {
+    if crate::db::fetch::fetch_opted_out(db_client_control, &follow_up.num).await? {
+        info!("Follow-up #{} cancelled, {} opted out", follow_up.id, follow_up.num);
+        close_follow_up(db_client_control, follow_up.id, "cancelled", "number opted out").await?;
+        return Ok(());
+    }
+    if state.env_vars.nmp_blocked_tipos.contains(&follow_up.tipo) {
+        let nmp_number = nmp::normalize_number(&follow_up.num).unwrap_or_else(|| follow_up.num.clone());
+        if crate::db::fetch::fetch_nmp_listed(db_client_control, &nmp_number).await? {
+            info!("Follow-up #{} cancelled, {} is on the Não Me Perturbe list", follow_up.id, follow_up.num);
+            close_follow_up(db_client_control, follow_up.id, "cancelled", "number on the Não Me Perturbe list").await?;
+            return Ok(());
+        }
+    }
+
+    let tenant = match &follow_up.tenant_id {
+        Some(id) => state.tenants.get(db_client_control, id).await?,
+        None => None,
+    };
+    if tenant.as_ref().is_some_and(|t| !t.enabled) {
+        close_follow_up(db_client_control, follow_up.id, "cancelled", "tenant disabled").await?;
+        return Ok(());
+    }
+    let (reply, log_message) = match tenant.as_ref().and_then(|t| t.replies.get("LEMBRETE")) {
+        Some(entry) => (entry.reply.clone(), entry.log_message.clone()),
+        None => (Some(REMINDER_REPLY.to_string()), REMINDER_LOG.to_string()),
+    };
+    let Some(reply) = reply else {
+        close_follow_up(db_client_control, follow_up.id, "cancelled", "tenant has no reminder").await?;
+        return Ok(());
+    };
+
+    // Replies handled by other consumers or apps never reach `cancel_follow_ups`, but they do
+    // update `customer_activity`.
+    let last_inbound = crate::db::fetch::fetch_last_inbound(db_client_control, &follow_up.num).await?;
+    if let (Some(inbound), Some(started)) = (last_inbound, follow_up.created_at) && inbound > started {
+        info!("Follow-up #{} closed, {} has written since it started", follow_up.id, follow_up.num);
+        close_follow_up(db_client_control, follow_up.id, "answered", "inbound message since the question").await?;
+        return Ok(());
+    }
+    if !service_window::is_open(last_inbound) {
+        info!("Follow-up #{} closed, the 24h window for {} has expired", follow_up.id, follow_up.num);
+        close_follow_up(db_client_control, follow_up.id, "expired", "outside the 24h window").await?;
+        return Ok(());
+    }
+
+    if let Some(opening) = quiet_hours::deferred_until(db_client_control, &state.env_vars, &follow_up.source).await? {
+        info!("Follow-up #{} outside the send window, moved to {}", follow_up.id, opening);
+        reschedule_follow_up(db_client_control, follow_up.id, opening, None).await?;
+        return Ok(());
+    }
+
+    // Logged as LEMBRETE, which also keeps the delivery from starting a new follow-up.
+    let message = OutboundMessage::Text(reply);
+    let entry = NewOutboxEntry {
+        num: &follow_up.num,
+        source: &follow_up.source,
+        tenant_id: follow_up.tenant_id.as_deref(),
+        tipo: "LEMBRETE",
+        message: &message,
+        log: Some(OutboxLog { log_message: &log_message, button_text: "", attribution: &Attribution::default() }),
+    };
+    if let (id, Err(e)) = outbox::send_recorded(state, db_client, db_client_control, &entry).await? {
+        warn!("Reminder for follow-up #{} left in outbox #{} for retry: {}", follow_up.id, id, e);
+    }
+
+    let sent = follow_up.reminders_sent + 1;
+    let next_at = (sent < state.env_vars.follow_up_max_reminders)
+        .then(|| Utc::now() + ChronoDuration::minutes(state.env_vars.follow_up_delay_mins));
+    info!("{} reminder {}/{} to {} handed to the outbox", follow_up.tipo, sent, state.env_vars.follow_up_max_reminders, follow_up.num);
+    record_reminder(db_client_control, follow_up.id, next_at).await?;
+    Ok(())
+}
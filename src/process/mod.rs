@@ -1 +1,15 @@
-pub mod process;
\ No newline at end of file
+pub mod audit;
+pub mod clock;
+pub mod events;
+pub mod follow_up;
+pub mod nmp;
+pub mod optout;
+pub mod outbox;
+pub mod partners;
+pub mod process;
+pub mod quiet_hours;
+pub mod scheduler;
+pub mod service_window;
+pub mod shadow;
+pub mod state;
+pub mod template;
\ No newline at end of file
@@ -0,0 +1,81 @@
+use super::process::Route;
+use crate::db::fetch::Tenant;
+
+const NMP_ACK_REPLY: &str = "Recebemos sua resposta, obrigado!";
+
+/// Canonical form shared by the registry import and the lookups: 55 + DDD + number, with
+/// the ninth digit on mobiles. Registry exports list DDD + 9 + number, while WhatsApp ids
+/// carry the country code and often leave the 9 out, so an 8-digit number starting with
+/// 6-9 (a mobile; landlines start with 2-5) gets it back.
+pub fn normalize_number(raw: &str) -> Option<String> {
+    let digits: String = raw.chars().filter(char::is_ascii_digit).collect();
+    let digits = match digits.len() {
+        10 | 11 => format!("55{}", digits),
+        12 | 13 if digits.starts_with("55") => digits,
+        _ => return None,
+    };
+    let (ddd, number) = digits[2..].split_at(2);
+    match number.len() {
+        8 if number.starts_with(['6', '7', '8', '9']) => Some(format!("55{}9{}", ddd, number)),
+        8 => Some(digits),
+        _ if number.starts_with('9') => Some(digits),
+        _ => None,
+    }
+}
+
+/// Replaces a credit-offer reply with a neutral acknowledgement (the tenant's `NMP`
+/// catalogue entry when there is one) and records why the offer was blocked.
+pub fn blocked_route(route: Route, tenant: Option<&Tenant>) -> Route {
+    let catalogue = tenant.and_then(|t| t.replies.get("NMP"));
+    Route {
+        reply: match catalogue {
+            Some(entry) => entry.reply.clone(),
+            None => Some(NMP_ACK_REPLY.to_string()),
+        },
+        log_message: format!("RESPOSTA {} BLOQUEADA: NÚMERO NO NÃO ME PERTURBE", route.tipo),
+        tipo: "NMP".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_the_country_code() {
+        assert_eq!(normalize_number("11988887777").as_deref(), Some("5511988887777"));
+        assert_eq!(normalize_number("1133334444").as_deref(), Some("551133334444"));
+    }
+
+    #[test]
+    fn adds_the_ninth_digit_to_mobiles() {
+        assert_eq!(normalize_number("1188887777").as_deref(), Some("5511988887777"));
+        assert_eq!(normalize_number("551188887777").as_deref(), Some("5511988887777"));
+        assert_eq!(normalize_number("5511988887777").as_deref(), Some("5511988887777"));
+    }
+
+    #[test]
+    fn keeps_landlines_without_a_ninth_digit() {
+        assert_eq!(normalize_number("551133334444").as_deref(), Some("551133334444"));
+    }
+
+    #[test]
+    fn wa_id_and_registry_forms_agree() {
+        assert_eq!(normalize_number("556188887777"), normalize_number("61988887777"));
+    }
+
+    #[test]
+    fn accepts_formatted_input() {
+        assert_eq!(normalize_number("+55 (11) 98888-7777").as_deref(), Some("5511988887777"));
+        assert_eq!(normalize_number("(11) 8888-7777").as_deref(), Some("5511988887777"));
+    }
+
+    #[test]
+    fn rejects_other_lengths_and_countries() {
+        assert_eq!(normalize_number("988887777"), None);
+        assert_eq!(normalize_number("14155550123"), None);
+        assert_eq!(normalize_number("4915112345678"), None);
+        assert_eq!(normalize_number("5511888877776"), None);
+        assert_eq!(normalize_number(""), None);
+    }
+}
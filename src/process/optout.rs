@@ -0,0 +1,129 @@
+use super::process::{ButtonClick, Route};
+use crate::db::fetch::Tenant;
+
+const OPTOUT_REPLY: &str = "Tudo certo! Você não receberá mais mensagens deste número.";
+const OPTOUT_LOG: &str = "CLIENTE PEDIU PARA NÃO RECEBER MAIS MENSAGENS";
+const SUPPRESSED_LOG: &str = "ENVIO BLOQUEADO: NÚMERO NA LISTA DE OPT-OUT";
+
+/// Lowercases and replaces punctuation with spaces so "Parar!" and "não  quero." match.
+fn normalize(text: &str) -> String {
+    let cleaned: String = text
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    cleaned.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The message split at punctuation, each clause normalized. "Não, quero sim" is two
+/// clauses, so the phrase "não quero" cannot span them.
+fn clauses(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && !c.is_whitespace())
+        .map(normalize)
+        .filter(|clause| !clause.is_empty())
+        .collect()
+}
+
+/// The configured keyword found in the button text or message body. A single word matches
+/// as a whole word in any clause; a phrase must be a whole clause, so "não quero perder"
+/// does not match "não quero".
+pub fn matched_keyword<'a>(keywords: &'a [String], click: &ButtonClick) -> Option<&'a str> {
+    let clauses = clauses(&click.button_text);
+    keywords
+        .iter()
+        .find(|keyword| {
+            let keyword = normalize(keyword);
+            if keyword.contains(' ') {
+                clauses.contains(&keyword)
+            } else {
+                !keyword.is_empty() && clauses.iter().any(|clause| clause.split(' ').any(|word| word == keyword))
+            }
+        })
+        .map(String::as_str)
+}
+
+/// The route for an opt-out request. The confirmation comes from the tenant's `OPTOUT`
+/// catalogue entry when there is one and is only sent the first time.
+pub fn confirmation_route(tenant: Option<&Tenant>, first_time: bool) -> Route {
+    let catalogue = tenant.and_then(|t| t.replies.get("OPTOUT"));
+    Route {
+        tipo: "OPTOUT".to_string(),
+        reply: match catalogue {
+            Some(entry) => entry.reply.clone(),
+            None => Some(OPTOUT_REPLY.to_string()),
+        }
+        .filter(|_| first_time),
+        log_message: catalogue.map(|entry| entry.log_message.clone()).unwrap_or_else(|| OPTOUT_LOG.to_string()),
+    }
+}
+
+/// Replaces the route of a click from an opted-out number: logged, never sent.
+pub fn suppressed_route() -> Route {
+    Route {
+        tipo: "OPTOUT".to_string(),
+        reply: None,
+        log_message: SUPPRESSED_LOG.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::process::{MessageKind, Provider};
+
+    fn text(body: &str) -> ButtonClick {
+        ButtonClick {
+            provider: Provider::Meta,
+            kind: MessageKind::Text,
+            source: "5511999990000".to_string(),
+            from: "5511988887777".to_string(),
+            contact_name: None,
+            app_id: None,
+            phone_number_id: None,
+            message_id: "wamid.text".to_string(),
+            timestamp: "1760875200".to_string(),
+            button_text: body.to_string(),
+            button_payload: String::new(),
+            context_id: String::new(),
+            gs_id: String::new(),
+            meta_msg_id: String::new(),
+        }
+    }
+
+    fn keywords() -> Vec<String> {
+        ["sair", "parar", "não quero"].iter().map(|k| k.to_string()).collect()
+    }
+
+    #[test]
+    fn matches_whole_words_only() {
+        assert_eq!(matched_keyword(&keywords(), &text("quero sair")), Some("sair"));
+        assert_eq!(matched_keyword(&keywords(), &text("sairemos amanhã")), None);
+        assert_eq!(matched_keyword(&keywords(), &text("desparar")), None);
+    }
+
+    #[test]
+    fn ignores_case_and_punctuation() {
+        assert_eq!(matched_keyword(&keywords(), &text("PARAR!")), Some("parar"));
+        assert_eq!(matched_keyword(&keywords(), &text("Por favor, sair.")), Some("sair"));
+    }
+
+    #[test]
+    fn matches_multi_word_keywords_as_a_whole_clause() {
+        assert_eq!(matched_keyword(&keywords(), &text("Não  quero, obrigado")), Some("não quero"));
+        assert_eq!(matched_keyword(&keywords(), &text("NÃO QUERO!")), Some("não quero"));
+        assert_eq!(matched_keyword(&keywords(), &text("quero não")), None);
+    }
+
+    #[test]
+    fn phrases_do_not_span_punctuation_or_match_inside_a_clause() {
+        assert_eq!(matched_keyword(&keywords(), &text("não, quero sim")), None);
+        assert_eq!(matched_keyword(&keywords(), &text("não quero perder")), None);
+        assert_eq!(matched_keyword(&keywords(), &text("Não quero perder essa oferta!")), None);
+    }
+
+    #[test]
+    fn empty_keyword_never_matches() {
+        let keywords = vec![" ".to_string(), "!".to_string()];
+        assert_eq!(matched_keyword(&keywords, &text("qualquer coisa")), None);
+    }
+}
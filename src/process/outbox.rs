@@ -0,0 +1,220 @@
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use deadpool_postgres::Object;
+use log::{info, error, warn};
+
+use super::{follow_up, quiet_hours, scheduler, service_window};
+use super::process::{provider_settings, Unavailable};
+use super::state::AppState;
+use crate::api::api::{provider_message_id, OutboundMessage};
+use crate::db::outbox::{
+    claim_due_outbox, defer_outbox, insert_with_log, mark_outbox_cancelled, mark_outbox_failed, mark_outbox_sent,
+    reconcile_stale, NewOutboxEntry, OutboxEntry
+};
+
+/// Rows claimed per run and logs database.
+const BATCH_SIZE: i64 = 50;
+const MAX_ATTEMPTS: i32 = 5;
+const RETRY_DELAY_MINUTES: i64 = 1;
+
+enum Recheck {
+    Send,
+    Cancel(&'static str),
+    Defer(DateTime<Utc>),
+}
+
+/// Sends a claimed outbox row and records the outcome in `db_client_logs`, the database the
+/// row lives in. Returns the provider response; failed sends are put back for the scheduler
+/// until `MAX_ATTEMPTS`.
+pub async fn deliver(
+    state: &AppState,
+    db_client: &Object,
+    db_client_control: &Object,
+    db_client_logs: &Object,
+    entry: &OutboxEntry
+) -> Result<String, String> {
+    let result = send(state, db_client, db_client_control, entry.tenant_id.as_deref(), &entry.source, &entry.num, &entry.message)
+        .await
+        .map_err(|e| e.to_string());
+
+    match result {
+        Ok(response) => {
+            info!("Outbox #{} delivered to {}", entry.id, entry.num);
+            let message_id = provider_message_id(&response);
+            if let Err(e) = mark_outbox_sent(db_client_logs, entry.id, message_id.as_deref(), &response).await {
+                error!("Failed to mark outbox #{} as sent: {}", entry.id, e);
+            }
+            if let Err(e) = follow_up::start(db_client_control, &state.env_vars, &entry.num, &entry.source, entry.tenant_id.as_deref(), &entry.tipo).await {
+                error!("Failed to start follow-up for {}: {}", entry.num, e);
+            }
+            Ok(response)
+        }
+        Err(e) => {
+            let retry_at = (entry.attempts < MAX_ATTEMPTS).then(|| Utc::now() + ChronoDuration::minutes(RETRY_DELAY_MINUTES * entry.attempts as i64));
+            match retry_at {
+                Some(retry_at) => warn!("Outbox #{} to {} failed (attempt {}), retrying at {}: {}", entry.id, entry.num, entry.attempts, retry_at, e),
+                None => error!("Outbox #{} to {} failed after {} attempts: {}", entry.id, entry.num, entry.attempts, e),
+            }
+            if let Err(e) = mark_outbox_failed(db_client_logs, entry.id, &e, retry_at).await {
+                error!("Failed to update outbox #{}: {}", entry.id, e);
+            }
+            Err(format!("outbox #{}: {}", entry.id, e))
+        }
+    }
+}
+
+/// Sends a reply whose outbox row could not be written because the logs database is down.
+/// There is no row to retry from, so a failed send is `Unavailable` and the click is
+/// requeued; the caller spools the log only once the reply is out.
+pub async fn deliver_unrecorded(
+    state: &AppState,
+    db_client: &Object,
+    db_client_control: &Object,
+    entry: &NewOutboxEntry<'_>
+) -> Result<String, Box<dyn std::error::Error>> {
+    let response = send(state, db_client, db_client_control, entry.tenant_id, entry.source, entry.num, entry.message)
+        .await
+        .map_err(|e| Unavailable(format!("logs database down and the reply failed: {}", e)))?;
+    info!("Reply delivered to {} without an outbox row", entry.num);
+    if let Err(e) = follow_up::start(db_client_control, &state.env_vars, entry.num, entry.source, entry.tenant_id, entry.tipo).await {
+        error!("Failed to start follow-up for {}: {}", entry.num, e);
+    }
+    Ok(response)
+}
+
+/// Records a reply the scheduler sends on its own (a deferred reply or a follow-up reminder)
+/// in the tenant's logs database, then delivers it. Once the row is written a failed send is
+/// retried from the outbox, so only a failure to record it is an error. Returns the outbox
+/// id and the outcome of the first attempt.
+pub async fn send_recorded(
+    state: &AppState,
+    db_client: &Object,
+    db_client_control: &Object,
+    entry: &NewOutboxEntry<'_>
+) -> Result<(i64, Result<String, String>), Box<dyn std::error::Error>> {
+    let tenant = match entry.tenant_id {
+        Some(id) => state.tenants.get(db_client_control, id).await?,
+        None => None,
+    };
+    let logs_db_url = tenant.and_then(|t| t.logs_db_url).unwrap_or_else(|| state.env_vars.db_url_logs.clone());
+    let mut db_client_logs = state.pools.client(&logs_db_url).await?;
+    let row = insert_with_log(&mut db_client_logs, entry).await?;
+    info!("{} reply to {} recorded as outbox #{}", entry.tipo, entry.num, row.id);
+    let delivered = deliver(state, db_client, db_client_control, &db_client_logs, &row).await;
+    Ok((row.id, delivered))
+}
+
+async fn send(
+    state: &AppState,
+    db_client: &Object,
+    db_client_control: &Object,
+    tenant_id: Option<&str>,
+    source: &str,
+    num: &str,
+    message: &OutboundMessage
+) -> Result<String, Box<dyn std::error::Error>> {
+    let tenant = match tenant_id {
+        Some(id) => state.tenants.get(db_client_control, id).await?,
+        None => None,
+    };
+    let conn = state
+        .sources
+        .get(db_client, db_client_control, source)
+        .await?
+        .ok_or("No connection found for source")?;
+    let settings = provider_settings(&conn, tenant.as_ref(), &state.env_vars)?;
+    crate::api::api::send_outbound(&settings, message, &conn.source, num).await
+}
+
+/// Settles stale claims and retries due rows in the control logs database and, with
+/// `MULTI_TENANT`, every tenant logs database. Called from the scheduler loop.
+pub async fn deliver_due(state: &AppState, db_client: &Object, db_client_control: &Object) -> Result<(), Box<dyn std::error::Error>> {
+    let mut urls = vec![state.env_vars.db_url_logs.clone()];
+    if state.env_vars.multi_tenant {
+        for (_, url) in state.tenants.logs_db_urls(db_client_control).await? {
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+
+    for url in urls {
+        let db_client_logs = match state.pools.client(&url).await {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Skipping outbox run for a logs database: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = deliver_due_in(state, db_client, db_client_control, &db_client_logs).await {
+            error!("Outbox run failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+async fn deliver_due_in(
+    state: &AppState,
+    db_client: &Object,
+    db_client_control: &Object,
+    db_client_logs: &Object
+) -> Result<(), tokio_postgres::Error> {
+    let resend = state.env_vars.outbox_resend_unconfirmed;
+    let stale = reconcile_stale(db_client_logs, resend).await?;
+    if stale > 0 && resend {
+        warn!("{} outbox row(s) were stuck in sending and will be sent again", stale);
+    } else if stale > 0 {
+        error!("{} outbox row(s) were stuck in sending and are now unconfirmed", stale);
+    }
+
+    let due = claim_due_outbox(db_client_logs, BATCH_SIZE).await?;
+    if !due.is_empty() {
+        info!("Retrying {} outbox row(s)", due.len());
+    }
+    for entry in due {
+        let recheck = recheck(state, db_client_control, &entry)
+            .await
+            .map_err(|e| e.to_string());
+        let updated = match recheck {
+            Ok(Recheck::Send) => {
+                let _ = deliver(state, db_client, db_client_control, db_client_logs, &entry).await;
+                continue;
+            }
+            Ok(Recheck::Cancel(reason)) => {
+                warn!("Outbox #{} to {} cancelled: {}", entry.id, entry.num, reason);
+                mark_outbox_cancelled(db_client_logs, entry.id, reason).await
+            }
+            Ok(Recheck::Defer(until)) => {
+                info!("Outbox #{} to {} held until {} by quiet hours", entry.id, entry.num, until);
+                defer_outbox(db_client_logs, entry.id, until).await
+            }
+            Err(e) => {
+                warn!("Could not recheck outbox #{}, retrying later: {}", entry.id, e);
+                defer_outbox(db_client_logs, entry.id, Utc::now() + ChronoDuration::minutes(RETRY_DELAY_MINUTES)).await
+            }
+        };
+        if let Err(e) = updated {
+            error!("Failed to update outbox #{}: {}", entry.id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Repeats the checks a deferred reply goes through before a retry, since the number may
+/// have opted out, the tenant may have been disabled or quiet hours may have started since
+/// the row was written. A free-form reply whose 24h window closed meanwhile is dropped, as
+/// the provider would reject it. An opt-out confirmation is never held by quiet hours.
+async fn recheck(state: &AppState, db_client_control: &Object, entry: &OutboxEntry) -> Result<Recheck, Box<dyn std::error::Error>> {
+    if let Some(reason) = scheduler::blocked_reason(state, db_client_control, &entry.num, &entry.tipo, entry.tenant_id.as_deref()).await? {
+        return Ok(Recheck::Cancel(reason));
+    }
+    if let OutboundMessage::Text(_) = entry.message {
+        let last_inbound = crate::db::fetch::fetch_last_inbound(db_client_control, &entry.num).await?;
+        if !service_window::is_open(last_inbound) {
+            return Ok(Recheck::Cancel("outside the 24h window"));
+        }
+    }
+    if entry.tipo != "OPTOUT" && let Some(opening) = quiet_hours::deferred_until(db_client_control, &state.env_vars, &entry.source).await? {
+        return Ok(Recheck::Defer(opening));
+    }
+    Ok(Recheck::Send)
+}
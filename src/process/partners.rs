@@ -0,0 +1,102 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use deadpool_postgres::Object;
+use log::{info, error, warn};
+use std::time::Duration;
+
+use super::events::ClickProcessedEvent;
+use super::state::AppState;
+use crate::db::partners::{claim_due_partner_deliveries, enqueue_partner_deliveries, mark_attempt_failed, mark_delivered, PartnerDelivery};
+use crate::signature::signature::sign;
+
+/// Deliveries claimed per run.
+const BATCH_SIZE: i64 = 50;
+const MAX_ATTEMPTS: i32 = 10;
+const RETRY_BASE_SECS: i64 = 30;
+const RETRY_MAX_SECS: i64 = 3600;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Queues the event for the partner endpoints of the click's source.
+pub async fn enqueue(db_client_control: &Object, event: &ClickProcessedEvent<'_>) {
+    let payload = match serde_json::to_string(event) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("Failed to serialize {} event for partners: {}", event.event, e);
+            return;
+        }
+    };
+    if let Err(e) = enqueue_partner_deliveries(db_client_control, event.source, event.event, &payload).await {
+        error!("Failed to queue partner deliveries for message {}: {}", event.message_id, e);
+    }
+}
+
+/// Posts the due partner deliveries. Called from the scheduler loop.
+pub async fn deliver_due(state: &AppState, db_client_control: &Object) -> Result<(), tokio_postgres::Error> {
+    let due = claim_due_partner_deliveries(db_client_control, BATCH_SIZE).await?;
+    if due.is_empty() {
+        return Ok(());
+    }
+
+    info!("Posting {} partner delivery(ies)", due.len());
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    for delivery in due {
+        deliver(state, &client, db_client_control, &delivery).await;
+    }
+    Ok(())
+}
+
+/// Capped exponential backoff: 30s, 1m, 2m, ... up to an hour between attempts.
+fn retry_delay(attempts: i32) -> ChronoDuration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    ChronoDuration::seconds((RETRY_BASE_SECS * 2_i64.pow(exponent)).min(RETRY_MAX_SECS))
+}
+
+async fn deliver(state: &AppState, client: &reqwest::Client, db_client_control: &Object, delivery: &PartnerDelivery) {
+    let result = post(state, client, delivery).await;
+
+    let updated = match result {
+        Ok(status_code) => {
+            info!("Partner delivery #{} to {} accepted ({})", delivery.id, delivery.partner, status_code);
+            mark_delivered(db_client_control, delivery.id, status_code).await
+        }
+        Err((status_code, e)) => {
+            let retry_at = (delivery.attempts < MAX_ATTEMPTS).then(|| Utc::now() + retry_delay(delivery.attempts));
+            match retry_at {
+                Some(retry_at) => warn!("Partner delivery #{} to {} failed (attempt {}), retrying at {}: {}", delivery.id, delivery.partner, delivery.attempts, retry_at, e),
+                None => error!("Partner delivery #{} to {} failed after {} attempts: {}", delivery.id, delivery.partner, delivery.attempts, e),
+            }
+            mark_attempt_failed(db_client_control, delivery.id, status_code, &e, retry_at).await
+        }
+    };
+
+    if let Err(e) = updated {
+        error!("Failed to update partner delivery #{}: {}", delivery.id, e);
+    }
+}
+
+/// Posts the payload signed with the partner's secret. Errors carry the HTTP status, if any.
+async fn post(state: &AppState, client: &reqwest::Client, delivery: &PartnerDelivery) -> Result<i32, (Option<i32>, String)> {
+    let secret = crate::crypto::secret::decrypt(state.env_vars.credentials_key.as_deref(), &delivery.secret)
+        .map_err(|e| (None, format!("Cannot decrypt partner secret: {}", e)))?;
+    let signature = format!("sha256={}", sign(&secret, delivery.payload.as_bytes()));
+
+    let response = client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header("X-Signature", signature)
+        .header("X-Delivery-Id", delivery.id.to_string())
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|e| (None, e.to_string()))?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(status.as_u16() as i32)
+    } else {
+        let body = response.text().await.unwrap_or_default();
+        Err((Some(status.as_u16() as i32), format!("status {}: {}", status, body.chars().take(500).collect::<String>())))
+    }
+}
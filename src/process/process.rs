@@ -1,11 +1,26 @@
 use serde::{Deserialize, Serialize};
-use log::{info, error};
+use log::{info, error, warn};
 use deadpool_postgres::Object;
+use super::audit::AuditRecord;
+use super::{nmp, optout, outbox, quiet_hours, service_window};
+use super::shadow::{Recorder, ShadowRecord};
+use super::state::AppState;
+use super::template::{render, TemplateVars};
+use crate::api::api::{OutboundMessage, ProviderSettings, GUPSHUP_MSG_ENDPOINT};
+use crate::config::config::EnvVars;
+use crate::db::fetch::{ProviderCredentials, SourceConnection, Tenant};
+use crate::db::log_writer::LogRecord;
+use crate::db::outbox::{NewOutboxEntry, OutboxLog};
+use crate::db::scheduled::NewScheduledSend;
+use chrono::{DateTime, TimeZone, Utc};
+use std::time::Instant;
 
+/// Envelope shared by Gupshup (which adds `gs_app_id` and `Context.gs_id`/`meta_msg_id`)
+/// and the native Meta WhatsApp Cloud API.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct WhatsAppWebhook {
     pub entry: Vec<Entry>,
-    pub gs_app_id: String,
+    pub gs_app_id: Option<String>,
     pub object: String,
 }
 
@@ -23,7 +38,9 @@ pub struct Change {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Value {
+    #[serde(default)]
     pub contacts: Vec<Contact>,
+    #[serde(default)]
     pub messages: Vec<Message>,
     pub messaging_product: String,
     pub metadata: Metadata,
@@ -46,10 +63,16 @@ pub struct Message {
     pub context: Option<Context>,
     pub from: String,
     pub id: String,
+    pub text: Option<Text>,
     pub timestamp: String,
     pub r#type: String,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Text {
+    pub body: String,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Button {
     pub payload: String,
@@ -59,9 +82,9 @@ pub struct Button {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Context {
     pub from: String,
-    pub gs_id: String,
+    pub gs_id: Option<String>,
     pub id: String,
-    pub meta_msg_id: String,
+    pub meta_msg_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -70,123 +93,879 @@ pub struct Metadata {
     pub phone_number_id: String,
 }
 
-pub fn parse_webhook_data(data: &[u8]) -> Result<Option<(String, String, String)>, Box<dyn std::error::Error>> {
+/// Gupshup v2 envelope: `{"type": "message", "payload": {..., "payload": {...}}}`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GupshupV2Webhook {
+    pub app: String,
+    pub timestamp: Option<i64>,
+    pub r#type: String,
+    pub payload: GupshupV2Message,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GupshupV2Message {
+    pub id: String,
+    pub source: String,
+    pub r#type: String,
+    pub payload: GupshupV2Content,
+    pub sender: GupshupV2Sender,
+    pub context: Option<GupshupV2Context>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GupshupV2Content {
+    pub text: Option<String>,
+    #[serde(alias = "postbackText")]
+    pub payload: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GupshupV2Sender {
+    pub phone: String,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GupshupV2Context {
+    pub id: String,
+    #[serde(rename = "gsId")]
+    pub gs_id: Option<String>,
+    pub from: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Provider {
+    Gupshup,
+    GupshupV2,
+    Meta,
+}
+
+/// Button replies are routed; free-text messages are only checked for opt-out keywords.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum MessageKind {
+    Button,
+    Text,
+}
+
+/// A button click (or inbound text) normalized from any of the supported webhook envelopes.
+/// For text messages `button_text` holds the message body and the context ids may be empty.
+///
+/// `source` is the business number the template was sent from. Gupshup v2 payloads do not
+/// carry it, so it is left empty and resolved from `app_id` before processing.
+#[derive(Debug, Clone)]
+pub struct ButtonClick {
+    pub provider: Provider,
+    pub kind: MessageKind,
+    pub source: String,
+    pub from: String,
+    pub contact_name: Option<String>,
+    pub app_id: Option<String>,
+    pub phone_number_id: Option<String>,
+    pub message_id: String,
+    pub timestamp: String,
+    pub button_text: String,
+    pub button_payload: String,
+    pub context_id: String,
+    pub gs_id: String,
+    pub meta_msg_id: String,
+}
+
+impl ButtonClick {
+    /// When the customer sent the message, from the webhook's epoch-seconds `timestamp`.
+    pub fn clicked_at(&self) -> Option<DateTime<Utc>> {
+        let secs = self.timestamp.trim().parse::<i64>().ok()?;
+        Utc.timestamp_opt(secs, 0).single()
+    }
+
+    /// Seconds between the customer's message and now.
+    pub fn lag_secs(&self) -> Option<i64> {
+        self.clicked_at().map(|at| (Utc::now() - at).num_seconds())
+    }
+}
+
+pub fn parse_webhook_data(data: &[u8]) -> Result<Option<ButtonClick>, Box<dyn std::error::Error>> {
     info!("Parsing webhook data");
     let json_data = String::from_utf8_lossy(data);
-    
-    let webhook: WhatsAppWebhook = match serde_json::from_str(&json_data) {
+
+    let raw: serde_json::Value = match serde_json::from_str(&json_data) {
         Ok(data) => data,
         Err(e) => {
             error!("Failed to deserialize webhook JSON: {}", e);
             return Err(Box::new(e));
         }
     };
-    
-    info!("Webhook deserialized successfully, processing {} entries", webhook.entry.len());
-    
+
+    let is_gupshup_v2 = raw.get("type").and_then(|t| t.as_str()) == Some("message")
+        && raw.get("payload").map(|p| p.is_object()).unwrap_or(false);
+
+    let result = if is_gupshup_v2 {
+        serde_json::from_value(raw).map(parse_gupshup_v2)
+    } else {
+        serde_json::from_value(raw).map(parse_whatsapp)
+    };
+
+    match result {
+        Ok(click) => Ok(click),
+        Err(e) => {
+            error!("Failed to deserialize webhook JSON: {}", e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+fn parse_whatsapp(webhook: WhatsAppWebhook) -> Option<ButtonClick> {
+    let provider = if webhook.gs_app_id.is_some() { Provider::Gupshup } else { Provider::Meta };
+    info!("{:?} webhook deserialized successfully, processing {} entries", provider, webhook.entry.len());
+
     for entry in webhook.entry {
         for change in entry.changes {
             if change.field == "messages" {
                 info!("Processing messages change");
-                
+                let contact_name = change.value.contacts.first().map(|c| c.profile.name.clone());
+                let metadata = change.value.metadata;
+
                 for message in change.value.messages {
-                    if let Some(context) = message.context {
-                        let context_from = context.from;
-                        let message_from = message.from;
-                        
-                        if let Some(button) = message.button {
-                            let button_text = button.text;
-                            info!("Found message with context and button, context.from: {}, message.from: {}, button.text: {}", context_from, message_from, button_text);
-                            return Ok(Some((context_from, message_from, button_text)));
-                        } else {
-                            info!("Message has context but no button, skipping");
+                    let (kind, button_text, button_payload) = match (message.button, message.text) {
+                        (Some(button), _) if message.context.is_some() => (MessageKind::Button, button.text, button.payload),
+                        (Some(_), _) => {
+                            info!("Message has button but no context, skipping");
+                            continue;
                         }
-                    } else {
-                        info!("Message has no context, skipping");
-                    }
+                        (None, Some(text)) => (MessageKind::Text, text.body, String::new()),
+                        (None, None) => {
+                            info!("Message of type {} has no button or text, skipping", message.r#type);
+                            continue;
+                        }
+                    };
+                    let context = message.context;
+                    let context_from = context.as_ref().map(|c| c.from.clone()).unwrap_or_default();
+                    info!("Found {:?} message, context.from: {}, message.from: {}, text: {}", kind, context_from, message.from, button_text);
+                    let source = if context_from.is_empty() { metadata.display_phone_number } else { context_from };
+                    return Some(ButtonClick {
+                        provider,
+                        kind,
+                        source,
+                        from: message.from,
+                        contact_name,
+                        app_id: webhook.gs_app_id,
+                        phone_number_id: Some(metadata.phone_number_id),
+                        message_id: message.id,
+                        timestamp: message.timestamp,
+                        button_text,
+                        button_payload,
+                        context_id: context.as_ref().map(|c| c.id.clone()).unwrap_or_default(),
+                        gs_id: context.as_ref().and_then(|c| c.gs_id.clone()).unwrap_or_default(),
+                        meta_msg_id: context.and_then(|c| c.meta_msg_id).unwrap_or_default(),
+                    });
                 }
             }
         }
     }
-    
+
     info!("No context.from found in webhook data");
-    Ok(None)
+    None
+}
+
+fn parse_gupshup_v2(webhook: GupshupV2Webhook) -> Option<ButtonClick> {
+    info!("GupshupV2 webhook deserialized successfully, message type {}", webhook.payload.r#type);
+    let message = webhook.payload;
+
+    let kind = match message.r#type.as_str() {
+        "quick_reply" | "button_reply" | "button" => MessageKind::Button,
+        "text" => MessageKind::Text,
+        _ => {
+            info!("Message is not a button reply or text, skipping");
+            return None;
+        }
+    };
+    let context = match (message.context, kind) {
+        (Some(context), _) => context,
+        (None, MessageKind::Text) => GupshupV2Context { id: String::new(), gs_id: None, from: None },
+        (None, MessageKind::Button) => {
+            info!("Message has no context, skipping");
+            return None;
+        }
+    };
+
+    let button_text = message.payload.text.unwrap_or_default();
+    let button_payload = match kind {
+        MessageKind::Button => message.payload.payload.unwrap_or_else(|| button_text.clone()),
+        MessageKind::Text => String::new(),
+    };
+    info!("Found {:?} message from {} on app {}, text: {}", kind, message.sender.phone, webhook.app, button_text);
+
+    Some(ButtonClick {
+        provider: Provider::GupshupV2,
+        kind,
+        source: context.from.unwrap_or_default(),
+        from: message.sender.phone,
+        contact_name: message.sender.name,
+        app_id: Some(webhook.app),
+        phone_number_id: None,
+        message_id: message.id,
+        timestamp: webhook.timestamp.map(|ms| (ms / 1000).to_string()).unwrap_or_default(),
+        button_text,
+        button_payload,
+        context_id: context.id,
+        gs_id: context.gs_id.unwrap_or_default(),
+        meta_msg_id: String::new(),
+    })
 }
 
+const BOLSA_REPLY: &str = "Vamos lá! Antes de realizar a consulta, é importante saber: o empréstimo do Bolsa Família pode chegar até R$650, caso o seu benefício esteja liberado.\n\nAtualmente, você recebe o Bolsa Família pelo aplicativo Caixa Tem?\n\nDigite:\n1️⃣ Para sim\n2️⃣ Para não";
+const BOLSA_LOG: &str = "Vamos lá! Antes de realizar a consulta, é importante saber: o empréstimo do Bolsa Família pode chegar até R$650, caso o seu benefício esteja liberado.\n\nAtualmente, você recebe o Bolsa Família pelo aplicativo Caixa Tem?\n\nDigite:\n1️⃣ Para sim\n2️⃣ Para não\n";
+const FGTS_REPLY: &str = "Perfeito! 😊\nAgora, você saberia me informar se ainda tem acesso ao aplicativo do FGTS?\n\nDigite:\n1️⃣ Para tenho acesso!\n2️⃣ Para não tenho!";
+const FGTS_LOG: &str = "Perfeito! Agora, você saberia me informar se ainda tem acesso ao aplicativo do FGTS?\n\nDigite: 1 para tenho acesso!\nDigite: 2 para não tenho!\n";
+const SEMINTERESSE_LOG: &str = "CLIENTE SEM INTERESSE OU RESPOSTA NÃO MAPEADA";
+const UNPROCESSED_LOG: &str = "BANCO DE DADOS INDISPONÍVEL: CLIQUE NÃO PROCESSADO, SEM RESPOSTA";
+
+/// How the outcome of a webhook is applied: `Live` sends the reply and writes the log,
+/// `DryRun` only reports what would have happened and `Shadow` hands it to a recorder.
+#[derive(Debug, Clone)]
+pub enum Mode {
+    Live,
+    DryRun,
+    Shadow(Recorder),
+}
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub tipo: String,
+    pub reply: Option<String>,
+    pub log_message: String,
+}
+
+impl Route {
+    fn new(tipo: &str, reply: Option<&str>, log_message: &str) -> Self {
+        Route { tipo: tipo.to_string(), reply: reply.map(str::to_string), log_message: log_message.to_string() }
+    }
+
+    /// Logged without a reply when a tipo has no entry in the catalogue.
+    fn unmapped() -> Self {
+        Route::new("SEMINTERESSE", None, SEMINTERESSE_LOG)
+    }
+
+    /// Replaces the reply to a click older than `CLICK_MAX_AGE_SECS`. A catalogue entry for
+    /// `EXPIRADO` handles it like any other route; otherwise it is only logged.
+    fn expired(tenant: Option<&Tenant>, lag_secs: i64) -> Self {
+        route_for_tipo("EXPIRADO", tenant).unwrap_or_else(|| {
+            Route::new("EXPIRADO", None, &format!("CLIQUE EXPIRADO: PROCESSADO {}s APÓS O ENVIO, SEM RESPOSTA", lag_secs))
+        })
+    }
+
+    /// Renders the reply and log message placeholders for one click.
+    pub fn personalize(self, vars: &TemplateVars) -> Self {
+        Route {
+            reply: self.reply.map(|reply| render(&reply, vars)),
+            log_message: render(&self.log_message, vars),
+            ..self
+        }
+    }
+}
+
+/// Looks a tipo up in the tenant's reply catalogue. A tenant without catalogue entries
+/// (and the single-tenant setup) uses the built-in BOLSA/FGTS copy.
+pub fn route_for_tipo(tipo: &str, tenant: Option<&Tenant>) -> Option<Route> {
+    if let Some(tenant) = tenant.filter(|t| !t.replies.is_empty()) {
+        return tenant
+            .replies
+            .get(tipo)
+            .map(|entry| Route::new(tipo, entry.reply.as_deref(), &entry.log_message));
+    }
+
+    match tipo {
+        "BOLSA" => Some(Route::new("BOLSA", Some(BOLSA_REPLY), BOLSA_LOG)),
+        "FGTS" => Some(Route::new("FGTS", Some(FGTS_REPLY), FGTS_LOG)),
+        "SEMINTERESSE" => Some(Route::unmapped()),
+        _ => None,
+    }
+}
+
+/// Fallback routing on the button's display text, used when no payload/template rule matches.
+pub fn route_for_button(button_text: &str, tenant: Option<&Tenant>) -> Route {
+    let button_text_lwr = button_text.to_lowercase();
+    let tipo = if button_text_lwr.contains("chamar") || button_text_lwr.contains("falar") {
+        "BOLSA"
+    } else if button_text_lwr.contains("vamos") || button_text_lwr.contains("saber") {
+        "FGTS"
+    } else {
+        "SEMINTERESSE"
+    };
+    route_for_tipo(tipo, tenant).unwrap_or_else(Route::unmapped)
+}
+
+/// Which campaign and template produced a click, stored on the interaction log row.
+#[derive(Debug, Clone, Default)]
+pub struct Attribution {
+    pub button_payload: String,
+    pub template_name: Option<String>,
+    pub campaign_id: Option<String>,
+    pub time_to_click_secs: Option<i64>,
+}
+
+/// Resolves the clicked message's context ids against the templates sent by the campaign system.
+pub async fn attribute_click(click: &ButtonClick, db_client: &Object) -> Result<Attribution, Box<dyn std::error::Error>> {
+    let message_ids: Vec<&str> = [&click.context_id, &click.gs_id, &click.meta_msg_id]
+        .into_iter()
+        .map(String::as_str)
+        .filter(|id| !id.is_empty())
+        .collect();
+
+    let mut attribution = Attribution {
+        button_payload: click.button_payload.clone(),
+        ..Attribution::default()
+    };
+
+    // template_messages belongs to the campaign system and may be missing or unreachable;
+    // the click is then routed without a template.
+    let sent = match crate::db::fetch::fetch_sent_template(db_client, &message_ids).await {
+        Ok(sent) => sent,
+        Err(e) => {
+            warn!("Campaign attribution unavailable, routing without a template: {}", e);
+            None
+        }
+    };
+    if let Some(sent) = sent {
+        let clicked_at = click.clicked_at().map(|at| at.timestamp());
+        attribution.time_to_click_secs = match (clicked_at, sent.sent_at_epoch) {
+            (Some(clicked_at), Some(sent_at)) => Some(clicked_at - sent_at),
+            _ => None,
+        };
+        attribution.template_name = Some(sent.template_name);
+        attribution.campaign_id = sent.campaign_id;
+    }
+
+    info!(
+        "Click attributed to campaign {:?}, template {:?}, time to click {:?}s",
+        attribution.campaign_id, attribution.template_name, attribution.time_to_click_secs
+    );
+    Ok(attribution)
+}
+
+/// Resolves the route from the `button_routes` rules (payload and originating template),
+/// falling back to the display text.
+pub async fn resolve_route(
+    click: &ButtonClick,
+    tenant: Option<&Tenant>,
+    template_name: Option<&str>,
+    db_client_logs: &Object
+) -> Result<Route, Box<dyn std::error::Error>> {
+    let tenant_id = tenant.map(|t| t.id.as_str());
+    let tipo = match crate::db::fetch::fetch_route_tipo(db_client_logs, tenant_id, template_name, &click.button_payload).await {
+        Ok(tipo) => tipo,
+        Err(e) => {
+            warn!("Route rule lookup failed, falling back to button text: {}", e);
+            None
+        }
+    };
+    if let Some(tipo) = tipo {
+        match route_for_tipo(&tipo, tenant) {
+            Some(route) => {
+                info!("Routed on payload/template rule to {}", route.tipo);
+                return Ok(route);
+            }
+            None => warn!("Route rule points to unknown tipo {}, falling back to button text", tipo),
+        }
+    }
+
+    let route = route_for_button(&click.button_text, tenant);
+    info!("Routed on button text to {}", route.tipo);
+    Ok(route)
+}
+
+/// Per-source credentials from `provider_credentials`, then the tenant's, falling back to
+/// `API_KEY_GUP`, the source's `parametros.source_name` and the public Gupshup endpoint.
+pub fn provider_settings(
+    conn: &SourceConnection,
+    tenant: Option<&Tenant>,
+    env_vars: &EnvVars
+) -> Result<ProviderSettings, Box<dyn std::error::Error>> {
+    let layers: Vec<&ProviderCredentials> = conn.credentials.iter().chain(tenant.map(|t| &t.credentials)).collect();
+    let pick = |field: fn(&ProviderCredentials) -> &Option<String>| layers.iter().find_map(|c| field(c).clone());
+
+    let api_key = match pick(|c| &c.api_key) {
+        Some(stored) => crate::crypto::secret::decrypt(env_vars.credentials_key.as_deref(), &stored)?,
+        None => env_vars.api_key_gup.clone(),
+    };
+
+    Ok(ProviderSettings {
+        api_key,
+        app_name: pick(|c| &c.app_name).unwrap_or_else(|| conn.source_name.clone()),
+        endpoint: pick(|c| &c.endpoint).unwrap_or_else(|| GUPSHUP_MSG_ENDPOINT.to_string()),
+    })
+}
+
+/// The click could not be handled for now: the main or control database is unreachable, or
+/// the logs database is down and the reply could neither be spooled nor sent. Nothing was
+/// recorded or sent, so the delivery can be requeued and tried again.
+#[derive(Debug)]
+pub struct Unavailable(pub(crate) String);
+
+impl std::fmt::Display for Unavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "database unavailable: {}", self.0)
+    }
+}
+
+impl std::error::Error for Unavailable {}
+
+/// Handles one webhook end to end. Database clients come from the shared pools: the main
+/// database, the control logs database (`DB_URL_LOGS`, which holds routes, credentials and
+/// tenants) and the tenant's own logs database when it has one.
 pub async fn process_webhook(
     data: &[u8],
-    db_client: &Object,
-    api_key_hug: &str,
-    api_key_gup: &str,
-    api_key_hug2: &str,
-    db_client_logs: &Object
+    state: &AppState,
+    mode: &Mode
+) -> Result<(), Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let mut audit = AuditRecord::new(data);
+    let mut logs_db_url = state.env_vars.db_url_logs.clone();
+
+    let result = match handle_click(data, state, mode, &mut audit, &mut logs_db_url).await {
+        Ok(()) => Ok(()),
+        Err(e) => match e.downcast::<Unavailable>() {
+            // Audited once the requeued delivery is handled.
+            Ok(unavailable) => return Err(unavailable),
+            Err(e) => Err(e.to_string()),
+        },
+    };
+
+    if matches!(mode, Mode::Live) {
+        audit.duration_ms = started.elapsed().as_millis() as i64;
+        audit.error = result.as_ref().err().cloned();
+        let db_client_logs = state.pools.client(&logs_db_url).await.map_err(|e| e.to_string());
+        let inserted = match db_client_logs {
+            Ok(db_client_logs) => crate::db::insert::insert_audit(&db_client_logs, &audit, state.env_vars.audit_compress_payload)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = inserted {
+            error!("Error when inserting audit record: {}", e);
+        }
+        if result.is_ok() && let Some(event) = super::events::processed_event(&audit) {
+            super::events::publish_processed(state, &event).await;
+            match state.pools.client(&state.env_vars.db_url_logs).await.map_err(|e| e.to_string()) {
+                Ok(db_client_control) => super::partners::enqueue(&db_client_control, &event).await,
+                Err(e) => error!("Failed to queue partner deliveries: {}", e),
+            }
+        }
+    }
+
+    result.map_err(Into::into)
+}
+
+async fn handle_click(
+    data: &[u8],
+    state: &AppState,
+    mode: &Mode,
+    audit: &mut AuditRecord,
+    logs_db_url: &mut String
 ) -> Result<(), Box<dyn std::error::Error>> {
     info!("Processing webhook...");
-    let (source, whatsapp_number, button_text, ) = match parse_webhook_data(data)? {
-        Some((context_from, message_from, button_text)) => (context_from, message_from, button_text),
+    let click = match parse_webhook_data(data)? {
+        Some(click) => click,
         None => {
             error!("No source (context.from) found in webhook");
             return Err("No source (context.from) found in webhook".into());
         }
     };
-    info!("Extracted source: {}, WhatsApp number: {}, button text: {}", source, whatsapp_number, button_text);
-    let button_text_lwr = button_text.to_lowercase();
+
+    let db_client = state.pools.client(&state.env_vars.db_url).await.map_err(|e| Unavailable(e.to_string()))?;
+    let db_client_control = state.pools.client(&state.env_vars.db_url_logs).await.map_err(|e| Unavailable(e.to_string()))?;
+
+    let mut click = click;
+    if click.source.is_empty() && let Some(app) = &click.app_id {
+        click.source = crate::db::fetch::fetch_source_by_app(&db_client, app).await?.unwrap_or_default();
+        info!("Resolved source {} for app {}", click.source, app);
+    }
+    audit.click = Some(click.clone());
+    audit.lag_secs = click.lag_secs();
+    match audit.lag_secs {
+        Some(lag_secs) => info!("Processing lag for message {}: {}s", click.message_id, lag_secs),
+        None => warn!("Message {} has no usable timestamp ({:?}), processing lag unknown", click.message_id, click.timestamp),
+    }
+    info!("Extracted {:?} click, source: {}, WhatsApp number: {}, button text: {}, payload: {}", click.provider, click.source, click.from, click.button_text, click.button_payload);
     info!("Button text validation passed, continuing with processing");
 
-    let uuid = match crate::db::fetch::fetch_uuid(db_client, &source).await? {
-        Some(u) => u,
-        None => {
-            error!("No uuid found for source: {}", source);
-            return Err("No uuid found for source".into());
+    let tenant = if state.env_vars.multi_tenant {
+        match state.tenants.resolve(&db_client_control, click.app_id.as_deref(), &click.source).await? {
+            Some(tenant) => Some(tenant),
+            None => {
+                error!("No tenant found for app {:?} / source {}", click.app_id, click.source);
+                return Err("No tenant found for click".into());
+            }
         }
+    } else {
+        None
     };
-    info!("Fetched uuid: {}", uuid);
+    if let Some(tenant) = &tenant {
+        audit.tenant_id = Some(tenant.id.clone());
+        if let Some(url) = &tenant.logs_db_url {
+            *logs_db_url = url.clone();
+        }
+        if !tenant.enabled {
+            warn!("Tenant {} is disabled, ignoring click from {}", tenant.id, click.from);
+            return Ok(());
+        }
+        info!("Click belongs to tenant {}", tenant.id);
+    }
+
+    let last_inbound = service_window::last_inbound(&db_client_control, &click, mode).await?;
+    if matches!(mode, Mode::Live) && crate::db::follow_ups::cancel_follow_ups(&db_client_control, &click.from).await? {
+        info!("{} answered, pending follow-up cancelled", click.from);
+    }
 
-    let conn = match crate::db::fetch::fetch_conn(db_client, &source).await? {
+    let opt_out_keyword = optout::matched_keyword(&state.env_vars.optout_keywords, &click);
+    if opt_out_keyword.is_none() && click.kind == MessageKind::Text {
+        info!("Text message from {} is not an opt-out request, nothing to do", click.from);
+        return Ok(());
+    }
+
+    let conn = match state.sources.get(&db_client, &db_client_control, &click.source).await? {
         Some(c) => c,
         None => {
-            error!("No connection found for source: {}", source);
+            error!("No connection found for source: {}", click.source);
             return Err("No connection found for source".into());
         }
     };
-    info!("Fetched connection: {}", conn);
+    info!("Fetched connection: {} (uuid {})", conn.source_name, conn.uuid);
 
-    let conn_tuple = (conn, source);
-    if button_text_lwr.contains("chamar") || button_text_lwr.contains("falar") {
-        crate::api::api::send_gupshup_message(api_key_gup, "Vamos lá! Antes de realizar a consulta, é importante saber: o empréstimo do Bolsa Família pode chegar até R$650, caso o seu benefício esteja liberado.\n\nAtualmente, você recebe o Bolsa Família pelo aplicativo Caixa Tem?\n\nDigite:\n1️⃣ Para sim\n2️⃣ Para não", conn_tuple, &whatsapp_number).await?;
-        match crate::db::insert::insert_log(&db_client_logs, &whatsapp_number, "Vamos lá! Antes de realizar a consulta, é importante saber: o empréstimo do Bolsa Família pode chegar até R$650, caso o seu benefício esteja liberado.\n\nAtualmente, você recebe o Bolsa Família pelo aplicativo Caixa Tem?\n\nDigite:\n1️⃣ Para sim\n2️⃣ Para não\n", &button_text, "BOLSA").await {
-            Ok(_) => {
-                info!("Contact creation process completed successfully");
-                Ok(())        
-            },
-            Err(e) => {
-                error!("Error when inserting log: {}",e);
-                Ok(())
-            }
+    let attribution = attribute_click(&click, &db_client).await?;
+    let vars = TemplateVars::for_click(&click, &conn);
+    let route = match opt_out_keyword {
+        Some(keyword) => {
+            let first_time = match mode {
+                Mode::Live => {
+                    let tenant_id = tenant.as_ref().map(|t| t.id.as_str());
+                    crate::db::insert::insert_opt_out(&db_client_control, &click.from, &click.source, tenant_id, keyword).await?
+                }
+                _ => !crate::db::fetch::fetch_opted_out(&db_client_control, &click.from).await?,
+            };
+            info!("{} asked to opt out with {:?} (first request: {})", click.from, keyword, first_time);
+            optout::confirmation_route(tenant.as_ref(), first_time)
         }
-    } else if button_text_lwr.contains("vamos") || button_text_lwr.contains("saber"){
-        
-        crate::api::api::send_gupshup_message(api_key_gup, "Perfeito! 😊\nAgora, você saberia me informar se ainda tem acesso ao aplicativo do FGTS?\n\nDigite:\n1️⃣ Para tenho acesso!\n2️⃣ Para não tenho!", conn_tuple, &whatsapp_number).await?;
-        match crate::db::insert::insert_log(&db_client_logs, &whatsapp_number, "Perfeito! Agora, você saberia me informar se ainda tem acesso ao aplicativo do FGTS?\n\nDigite: 1 para tenho acesso!\nDigite: 2 para não tenho!\n", &button_text, "FGTS").await {
-            Ok(_) => {
-                info!("Contact creation process completed successfully");
-                Ok(())        
-            },
-            Err(e) => {
-                error!("Error when inserting log: {}",e);
-                Ok(())
-            }
+        None if crate::db::fetch::fetch_opted_out(&db_client_control, &click.from).await? => {
+            warn!("{} is on the opt-out list, logging instead of replying", click.from);
+            optout::suppressed_route()
         }
+        None => match (audit.lag_secs, state.env_vars.click_max_age_secs) {
+            (Some(lag_secs), Some(max_age)) if lag_secs > max_age => {
+                warn!("Click from {} is {}s old (max {}s), handling it as expired", click.from, lag_secs, max_age);
+                Route::expired(tenant.as_ref(), lag_secs)
+            }
+            _ => resolve_route(&click, tenant.as_ref(), attribution.template_name.as_deref(), &db_client_control).await?,
+        },
+    }
+    .personalize(&vars);
+
+    let nmp_number = nmp::normalize_number(&click.from).unwrap_or_else(|| click.from.clone());
+    let route = if route.reply.is_some()
+        && state.env_vars.nmp_blocked_tipos.contains(&route.tipo)
+        && crate::db::fetch::fetch_nmp_listed(&db_client_control, &nmp_number).await?
+    {
+        warn!("{} is on the Não Me Perturbe list, blocking the {} offer", click.from, route.tipo);
+        nmp::blocked_route(route, tenant.as_ref()).personalize(&vars)
     } else {
-        match crate::db::insert::insert_log(&db_client_logs, &whatsapp_number, "CLIENTE SEM INTERESSE OU RESPOSTA NÃO MAPEADA", &button_text, "SEMINTERESSE").await {
-            Ok(_) => {
-                info!("Contact creation process completed successfully");
-                Ok(())        
-            },
+        route
+    };
+    info!("Button routed to {}", route.tipo);
+    audit.tipo = Some(route.tipo.clone());
+
+    let tenant_id = tenant.as_ref().map(|t| t.id.as_str());
+    let template = match route.reply {
+        Some(_) => service_window::route_template(&db_client_control, tenant_id, &route.tipo, &vars).await?,
+        None => None,
+    };
+    // An opt-out confirmation answers the customer's own message, so quiet hours don't hold it.
+    let deferred_until = match route.reply {
+        Some(_) if route.tipo != "OPTOUT" => quiet_hours::deferred_until(&db_client_control, &state.env_vars, &click.source).await?,
+        _ => None,
+    };
+    let mut route = route;
+    let outbound = match deferred_until {
+        Some(_) => None,
+        None => service_window::outbound_for(&mut route, last_inbound, template.clone()),
+    };
+    let described = outbound.as_ref().map(OutboundMessage::describe);
+
+    match mode {
+        Mode::Live => {}
+        Mode::DryRun => {
+            println!(
+                "[dry-run] to={} source={} button={:?} route={} reply={:?} deferred_until={:?}",
+                click.from, click.source, click.button_text, route.tipo, described.as_deref().unwrap_or("<none>"), deferred_until
+            );
+            return Ok(());
+        }
+        Mode::Shadow(recorder) => {
+            let record = ShadowRecord {
+                recorded_at: Utc::now().to_rfc3339(),
+                num: &click.from,
+                source: &click.source,
+                source_name: &conn.source_name,
+                button: &click.button_text,
+                tipo: &route.tipo,
+                reply: described.as_deref(),
+                log_message: &route.log_message,
+            };
+            let db_client_logs = state.pools.client(logs_db_url).await?;
+            return recorder.record(&record, &db_client_logs).await;
+        }
+    }
+
+    if let Some(send_after) = deferred_until {
+        let template = match &template {
+            Some(OutboundMessage::Template { id, params }) => Some((id.as_str(), params.as_slice())),
+            _ => None,
+        };
+        let send = NewScheduledSend {
+            num: &click.from,
+            source: &conn.source,
+            tenant_id,
+            tipo: &route.tipo,
+            reply: route.reply.as_deref(),
+            template,
+            send_after,
+        };
+        let id = crate::db::scheduled::insert_scheduled_send(&db_client_control, &send).await?;
+        info!("Outside the send window for {}, reply scheduled as #{} for {}", conn.source, id, send_after);
+        audit.provider_response = Some(format!("scheduled #{} for {}", id, send_after));
+    } else if let Some(outbound) = &outbound {
+        let entry = NewOutboxEntry {
+            num: &click.from,
+            source: &conn.source,
+            tenant_id,
+            tipo: &route.tipo,
+            message: outbound,
+            log: Some(OutboxLog {
+                log_message: &route.log_message,
+                button_text: &click.button_text,
+                attribution: &attribution,
+            }),
+        };
+        let recorded = match state.pools.client(logs_db_url).await.map_err(|e| e.to_string()) {
+            Ok(mut db_client_logs) => crate::db::outbox::insert_with_log(&mut db_client_logs, &entry)
+                .await
+                .map(|row| (db_client_logs, row))
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        let response = match recorded {
+            Ok((db_client_logs, row)) => {
+                info!("Reply to {} recorded as outbox #{}", click.from, row.id);
+                outbox::deliver(state, &db_client, &db_client_control, &db_client_logs, &row).await?
+            }
             Err(e) => {
-                error!("Error when inserting log: {}",e);
-                Ok(())
+                warn!("Could not record the reply to {} in the logs database: {}", click.from, e);
+                let log = log_record(&click, tenant_id, &route, &attribution);
+                state.spool.check_room(&log).await.map_err(|e| Unavailable(e.to_string()))?;
+                let response = outbox::deliver_unrecorded(state, &db_client, &db_client_control, &entry).await?;
+                // The reply is out, so the click must not be requeued; a log the spool refuses
+                // now waits in the log writer instead.
+                let spooled = state.spool.append(&log).await.map_err(|e| e.to_string());
+                if let Err(e) = spooled {
+                    error!("Could not spool the log for {}, handing it to the log writer: {}", click.from, e);
+                    state.log_writer.write(logs_db_url, log).await?;
+                }
+                response
             }
+        };
+        audit.provider_response = Some(response);
+        info!("Contact creation process completed successfully");
+        return Ok(());
+    }
+
+    state.log_writer.write(logs_db_url, log_record(&click, tenant_id, &route, &attribution)).await?;
+    info!("Contact creation process completed successfully");
+    Ok(())
+}
+
+/// Spools a click that was requeued `MAX_REQUEUES` times without a database, so the
+/// interaction is kept once the delivery is acked. No reply is sent for it, and its tenant is
+/// unknown, so the spool drains it into the control logs database.
+pub async fn spool_unprocessed(data: &[u8], state: &AppState) -> Result<(), Box<dyn std::error::Error>> {
+    let click = parse_webhook_data(data)?.ok_or("No source (context.from) found in webhook")?;
+    let log = LogRecord::new(
+        &click.message_id,
+        None,
+        &click.from,
+        UNPROCESSED_LOG,
+        &click.button_text,
+        "INDISPONIVEL",
+        &Attribution { button_payload: click.button_payload.clone(), ..Attribution::default() },
+    );
+    state.spool.append(&log).await
+}
+
+/// The interaction row, for the log writer or, when the logs database cannot take it, the
+/// local spool.
+fn log_record(click: &ButtonClick, tenant_id: Option<&str>, route: &Route, attribution: &Attribution) -> LogRecord {
+    LogRecord::new(&click.message_id, tenant_id, &click.from, &route.log_message, &click.button_text, &route.tipo, attribution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn whatsapp(gs_app_id: Option<&str>, message: serde_json::Value) -> Vec<u8> {
+        let mut webhook = serde_json::json!({
+            "object": "whatsapp_business_account",
+            "entry": [{
+                "id": "1234",
+                "changes": [{
+                    "field": "messages",
+                    "value": {
+                        "messaging_product": "whatsapp",
+                        "metadata": { "display_phone_number": "5511999990000", "phone_number_id": "10987" },
+                        "contacts": [{ "profile": { "name": "Maria Silva" }, "wa_id": "5511988887777" }],
+                        "messages": [message]
+                    }
+                }]
+            }]
+        });
+        if let Some(app) = gs_app_id {
+            webhook["gs_app_id"] = app.into();
+        }
+        serde_json::to_vec(&webhook).unwrap()
+    }
+
+    fn button_message(context: Option<serde_json::Value>) -> serde_json::Value {
+        let mut message = serde_json::json!({
+            "from": "5511988887777",
+            "id": "wamid.click",
+            "timestamp": "1760875200",
+            "type": "button",
+            "button": { "payload": "QUERO_BOLSA", "text": "Quero chamar" }
+        });
+        if let Some(context) = context {
+            message["context"] = context;
+        }
+        message
+    }
+
+    fn gupshup_v2(r#type: &str, payload: serde_json::Value, context: Option<serde_json::Value>) -> Vec<u8> {
+        let mut message = serde_json::json!({
+            "id": "gs.click",
+            "source": "5511988887777",
+            "type": r#type,
+            "payload": payload,
+            "sender": { "phone": "5511988887777", "name": "Maria Silva" }
+        });
+        if let Some(context) = context {
+            message["context"] = context;
         }
+        serde_json::to_vec(&serde_json::json!({
+            "app": "campanhas",
+            "timestamp": 1760875200123_i64,
+            "type": "message",
+            "payload": message
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn gupshup_button_click() {
+        let context = serde_json::json!({ "from": "5511999990000", "id": "ctx.1", "gs_id": "gs.1", "meta_msg_id": "meta.1" });
+        let click = parse_webhook_data(&whatsapp(Some("app-1"), button_message(Some(context)))).unwrap().unwrap();
+
+        assert_eq!(click.provider, Provider::Gupshup);
+        assert_eq!(click.kind, MessageKind::Button);
+        assert_eq!(click.source, "5511999990000");
+        assert_eq!(click.from, "5511988887777");
+        assert_eq!(click.app_id.as_deref(), Some("app-1"));
+        assert_eq!(click.contact_name.as_deref(), Some("Maria Silva"));
+        assert_eq!(click.button_text, "Quero chamar");
+        assert_eq!(click.button_payload, "QUERO_BOLSA");
+        assert_eq!((click.context_id.as_str(), click.gs_id.as_str(), click.meta_msg_id.as_str()), ("ctx.1", "gs.1", "meta.1"));
+    }
+
+    #[test]
+    fn meta_button_click() {
+        let context = serde_json::json!({ "from": "5511999990000", "id": "wamid.template" });
+        let click = parse_webhook_data(&whatsapp(None, button_message(Some(context)))).unwrap().unwrap();
+
+        assert_eq!(click.provider, Provider::Meta);
+        assert_eq!(click.kind, MessageKind::Button);
+        assert_eq!(click.app_id, None);
+        assert_eq!(click.phone_number_id.as_deref(), Some("10987"));
+        assert_eq!(click.context_id, "wamid.template");
+        assert_eq!(click.gs_id, "");
+        assert_eq!(click.clicked_at().map(|at| at.timestamp()), Some(1760875200));
+    }
+
+    #[test]
+    fn whatsapp_button_without_context_is_skipped() {
+        assert!(parse_webhook_data(&whatsapp(None, button_message(None))).unwrap().is_none());
+    }
+
+    #[test]
+    fn whatsapp_text_message_uses_business_number() {
+        let message = serde_json::json!({
+            "from": "5511988887777",
+            "id": "wamid.text",
+            "timestamp": "1760875200",
+            "type": "text",
+            "text": { "body": "parar" }
+        });
+        let click = parse_webhook_data(&whatsapp(None, message)).unwrap().unwrap();
+
+        assert_eq!(click.kind, MessageKind::Text);
+        assert_eq!(click.source, "5511999990000");
+        assert_eq!(click.button_text, "parar");
+        assert_eq!(click.button_payload, "");
+        assert_eq!(click.context_id, "");
+    }
+
+    #[test]
+    fn gupshup_v2_button_click() {
+        let payload = serde_json::json!({ "text": "Quero saber", "postbackText": "QUERO_FGTS" });
+        let context = serde_json::json!({ "id": "ctx.2", "gsId": "gs.2", "from": "5511999990000" });
+        let click = parse_webhook_data(&gupshup_v2("quick_reply", payload, Some(context))).unwrap().unwrap();
+
+        assert_eq!(click.provider, Provider::GupshupV2);
+        assert_eq!(click.kind, MessageKind::Button);
+        assert_eq!(click.source, "5511999990000");
+        assert_eq!(click.app_id.as_deref(), Some("campanhas"));
+        assert_eq!(click.button_text, "Quero saber");
+        assert_eq!(click.button_payload, "QUERO_FGTS");
+        assert_eq!((click.context_id.as_str(), click.gs_id.as_str()), ("ctx.2", "gs.2"));
+    }
+
+    #[test]
+    fn gupshup_v2_timestamp_is_converted_to_seconds() {
+        let payload = serde_json::json!({ "text": "Quero saber" });
+        let context = serde_json::json!({ "id": "ctx.2" });
+        let click = parse_webhook_data(&gupshup_v2("button_reply", payload, Some(context))).unwrap().unwrap();
+
+        assert_eq!(click.timestamp, "1760875200");
+        assert_eq!(click.clicked_at().map(|at| at.timestamp()), Some(1760875200));
+        // Without a postback payload the display text is used.
+        assert_eq!(click.button_payload, "Quero saber");
+        // Gupshup v2 carries no business number; it is resolved from the app later.
+        assert_eq!(click.source, "");
+    }
+
+    #[test]
+    fn gupshup_v2_text_message() {
+        let payload = serde_json::json!({ "text": "não quero" });
+        let click = parse_webhook_data(&gupshup_v2("text", payload, None)).unwrap().unwrap();
+
+        assert_eq!(click.kind, MessageKind::Text);
+        assert_eq!(click.button_text, "não quero");
+        assert_eq!(click.button_payload, "");
+    }
+
+    #[test]
+    fn gupshup_v2_skips_buttons_without_context_and_other_types() {
+        let payload = serde_json::json!({ "text": "Quero saber" });
+        assert!(parse_webhook_data(&gupshup_v2("quick_reply", payload.clone(), None)).unwrap().is_none());
+        assert!(parse_webhook_data(&gupshup_v2("image", payload, None)).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_json_is_an_error() {
+        assert!(parse_webhook_data(b"{not json").is_err());
+        assert!(parse_webhook_data(br#"{"type": "message"}"#).is_err());
     }
 }
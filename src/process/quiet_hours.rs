@@ -0,0 +1,128 @@
+use chrono::{DateTime, Datelike, Days, FixedOffset, NaiveDate, NaiveTime, Utc};
+use deadpool_postgres::Object;
+use log::warn;
+
+use super::clock::now_sao_paulo;
+use crate::config::config::EnvVars;
+use crate::db::fetch::{fetch_holidays, fetch_send_window, SendWindow};
+
+/// How far ahead to look for an open window before giving up and sending right away.
+const SEARCH_DAYS: u64 = 14;
+
+/// When a reply through `source` may be sent, or `None` when it can go out now.
+pub async fn deferred_until(
+    client_logs: &Object,
+    env_vars: &EnvVars,
+    source: &str
+) -> Result<Option<DateTime<Utc>>, tokio_postgres::Error> {
+    let window = match fetch_send_window(client_logs, source).await? {
+        Some(window) => window,
+        None => match env_vars.default_send_window {
+            Some((start, end)) => SendWindow { start, end, days: (1..=7).collect() },
+            None => return Ok(None),
+        },
+    };
+    if window.start >= window.end {
+        warn!("Send window for {} starts at or after its end ({}-{}), ignoring it", source, window.start, window.end);
+        return Ok(None);
+    }
+
+    let now = now_sao_paulo();
+    let today = now.date_naive();
+    let holidays = fetch_holidays(client_logs, today, today + Days::new(SEARCH_DAYS)).await?;
+    if is_open(now, &window, &holidays) {
+        return Ok(None);
+    }
+
+    let opening = next_opening(now, &window, &holidays);
+    if opening.is_none() {
+        warn!("No send window for {} opens in the next {} days, sending now", source, SEARCH_DAYS);
+    }
+    Ok(opening.map(|opening| opening.with_timezone(&Utc)))
+}
+
+fn is_open_day(day: NaiveDate, window: &SendWindow, holidays: &[NaiveDate]) -> bool {
+    window.days.contains(&(day.weekday().number_from_monday() as i16)) && !holidays.contains(&day)
+}
+
+/// Whether `now` falls inside the window on an allowed, non-holiday day.
+fn is_open(now: DateTime<FixedOffset>, window: &SendWindow, holidays: &[NaiveDate]) -> bool {
+    is_open_day(now.date_naive(), window, holidays) && in_window(now.time(), window)
+}
+
+/// The first window start after `now` within `SEARCH_DAYS`, skipping disallowed weekdays
+/// and holidays.
+fn next_opening(now: DateTime<FixedOffset>, window: &SendWindow, holidays: &[NaiveDate]) -> Option<DateTime<FixedOffset>> {
+    let today = now.date_naive();
+    (0..=SEARCH_DAYS)
+        .map(|offset| today + Days::new(offset))
+        .filter(|day| is_open_day(*day, window, holidays))
+        .filter_map(|day| day.and_time(window.start).and_local_timezone(now.timezone()).single())
+        .find(|opening| *opening > now)
+}
+
+/// Windows are same-day ranges; `end` is exclusive.
+fn in_window(time: NaiveTime, window: &SendWindow) -> bool {
+    time >= window.start && time < window.end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::clock::sao_paulo_offset;
+
+    /// 2026-10-19 is a Monday.
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<FixedOffset> {
+        NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+            .and_local_timezone(sao_paulo_offset())
+            .unwrap()
+    }
+
+    fn business_hours() -> SendWindow {
+        SendWindow {
+            start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            days: (1..=5).collect(),
+        }
+    }
+
+    #[test]
+    fn open_inside_the_window_on_a_weekday() {
+        assert!(is_open(at(19, 9, 0), &business_hours(), &[]));
+        assert!(is_open(at(19, 17, 59), &business_hours(), &[]));
+    }
+
+    #[test]
+    fn end_is_exclusive() {
+        assert!(!is_open(at(19, 18, 0), &business_hours(), &[]));
+        assert_eq!(next_opening(at(19, 18, 0), &business_hours(), &[]), Some(at(20, 9, 0)));
+    }
+
+    #[test]
+    fn before_the_window_opens_the_same_day() {
+        assert!(!is_open(at(19, 8, 59), &business_hours(), &[]));
+        assert_eq!(next_opening(at(19, 8, 59), &business_hours(), &[]), Some(at(19, 9, 0)));
+    }
+
+    #[test]
+    fn weekend_waits_for_monday() {
+        assert!(!is_open(at(24, 10, 0), &business_hours(), &[]));
+        assert_eq!(next_opening(at(23, 20, 0), &business_hours(), &[]), Some(at(26, 9, 0)));
+    }
+
+    #[test]
+    fn holidays_are_skipped() {
+        let holidays = [NaiveDate::from_ymd_opt(2026, 10, 20).unwrap()];
+        assert!(!is_open(at(20, 10, 0), &business_hours(), &holidays));
+        assert_eq!(next_opening(at(19, 18, 30), &business_hours(), &holidays), Some(at(21, 9, 0)));
+    }
+
+    #[test]
+    fn no_opening_without_allowed_days() {
+        let window = SendWindow { days: Vec::new(), ..business_hours() };
+        assert_eq!(next_opening(at(19, 10, 0), &window, &[]), None);
+    }
+}
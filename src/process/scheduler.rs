@@ -0,0 +1,150 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use deadpool_postgres::Object;
+use log::{info, error, warn};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{follow_up, nmp, outbox, partners};
+use super::service_window;
+use super::state::AppState;
+use crate::api::api::OutboundMessage;
+use crate::db::outbox::NewOutboxEntry;
+use crate::db::scheduled::{claim_due, mark_cancelled, mark_failed, mark_sent, ScheduledSend};
+
+/// Rows claimed per run.
+const BATCH_SIZE: i64 = 50;
+const MAX_ATTEMPTS: i32 = 5;
+const RETRY_DELAY_MINUTES: i64 = 5;
+
+enum Outcome {
+    Sent(String),
+    Cancelled(&'static str),
+}
+
+/// Delivers replies held back by quiet hours once their window opens, then outbox retries,
+/// due follow-up reminders and partner webhooks. Runs for the life of the process; failed sends are retried a few times
+/// before the row is marked `failed`.
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(state.env_vars.scheduler_interval_secs));
+    loop {
+        interval.tick().await;
+        if let Err(e) = run_once(&state).await {
+            error!("Scheduled sends run failed: {}", e);
+        }
+    }
+}
+
+async fn run_once(state: &AppState) -> Result<(), Box<dyn std::error::Error>> {
+    let db_client = state.pools.client(&state.env_vars.db_url).await?;
+    let db_client_control = state.pools.client(&state.env_vars.db_url_logs).await?;
+
+    let due = claim_due(&db_client_control, BATCH_SIZE).await?;
+    if !due.is_empty() {
+        info!("Delivering {} scheduled send(s)", due.len());
+    }
+    for send in due {
+        deliver(state, &db_client, &db_client_control, &send).await;
+    }
+
+    outbox::deliver_due(state, &db_client, &db_client_control).await?;
+    follow_up::deliver_due(state, &db_client, &db_client_control).await?;
+    partners::deliver_due(state, &db_client_control).await?;
+    Ok(())
+}
+
+async fn deliver(state: &AppState, db_client: &Object, db_client_control: &Object, send: &ScheduledSend) {
+    let result = send_reply(state, db_client, db_client_control, send)
+        .await
+        .map_err(|e| e.to_string());
+
+    let updated = match result {
+        Ok(Outcome::Sent(response)) => {
+            info!("Scheduled send #{} to {} handed to the outbox", send.id, send.num);
+            mark_sent(db_client_control, send.id, Some(&response)).await
+        }
+        Ok(Outcome::Cancelled(reason)) => {
+            warn!("Scheduled send #{} to {} cancelled: {}", send.id, send.num, reason);
+            mark_cancelled(db_client_control, send.id, reason).await
+        }
+        Err(e) if send.attempts < MAX_ATTEMPTS => {
+            warn!("Scheduled send #{} failed (attempt {}), retrying: {}", send.id, send.attempts, e);
+            let retry_at = Utc::now() + ChronoDuration::minutes(RETRY_DELAY_MINUTES);
+            mark_failed(db_client_control, send.id, &e, Some(retry_at)).await
+        }
+        Err(e) => {
+            error!("Scheduled send #{} failed after {} attempts: {}", send.id, send.attempts, e);
+            mark_failed(db_client_control, send.id, &e, None).await
+        }
+    };
+
+    if let Err(e) = updated {
+        error!("Failed to update scheduled send #{}: {}", send.id, e);
+    }
+}
+
+/// Why a reply that was held back may no longer be sent: the number opted out or is on the
+/// Não Me Perturbe list, or its tenant was disabled. Shared by deferred replies and outbox
+/// retries. An opt-out confirmation is exempt from the opt-out check, which it would always
+/// fail.
+pub async fn blocked_reason(
+    state: &AppState,
+    db_client_control: &Object,
+    num: &str,
+    tipo: &str,
+    tenant_id: Option<&str>
+) -> Result<Option<&'static str>, Box<dyn std::error::Error>> {
+    if tipo != "OPTOUT" && crate::db::fetch::fetch_opted_out(db_client_control, num).await? {
+        return Ok(Some("number opted out"));
+    }
+    if state.env_vars.nmp_blocked_tipos.iter().any(|blocked| blocked == tipo) {
+        let nmp_number = nmp::normalize_number(num).unwrap_or_else(|| num.to_string());
+        if crate::db::fetch::fetch_nmp_listed(db_client_control, &nmp_number).await? {
+            return Ok(Some("number on the Não Me Perturbe list"));
+        }
+    }
+
+    let tenant = match tenant_id {
+        Some(id) => state.tenants.get(db_client_control, id).await?,
+        None => None,
+    };
+    if tenant.as_ref().is_some_and(|t| !t.enabled) {
+        return Ok(Some("tenant disabled"));
+    }
+    Ok(None)
+}
+
+/// The compliance checks and 24h window are evaluated again, since any of them may have
+/// changed while the reply was waiting.
+async fn send_reply(
+    state: &AppState,
+    db_client: &Object,
+    db_client_control: &Object,
+    send: &ScheduledSend
+) -> Result<Outcome, Box<dyn std::error::Error>> {
+    if let Some(reason) = blocked_reason(state, db_client_control, &send.num, &send.tipo, send.tenant_id.as_deref()).await? {
+        return Ok(Outcome::Cancelled(reason));
+    }
+
+    let last_inbound = crate::db::fetch::fetch_last_inbound(db_client_control, &send.num).await?;
+    let message = match (&send.reply, &send.template_id) {
+        (Some(reply), _) if service_window::is_open(last_inbound) => OutboundMessage::Text(reply.clone()),
+        (_, Some(id)) => OutboundMessage::Template { id: id.clone(), params: send.template_params.clone() },
+        _ => return Ok(Outcome::Cancelled("outside the 24h window and no template configured")),
+    };
+
+    // The click was logged when the reply was deferred, so the outbox row carries no log.
+    info!("Sending scheduled {} reply #{} to {}", send.tipo, send.id, send.num);
+    let entry = NewOutboxEntry {
+        num: &send.num,
+        source: &send.source,
+        tenant_id: send.tenant_id.as_deref(),
+        tipo: &send.tipo,
+        message: &message,
+        log: None,
+    };
+    let response = match outbox::send_recorded(state, db_client, db_client_control, &entry).await? {
+        (_, Ok(response)) => response,
+        (id, Err(e)) => format!("outbox #{}, first attempt failed: {}", id, e),
+    };
+    Ok(Outcome::Sent(response))
+}
@@ -0,0 +1,77 @@
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::Object;
+use log::{info, warn};
+
+use super::process::{ButtonClick, Mode, Route};
+use super::template::{render, TemplateVars};
+use crate::api::api::OutboundMessage;
+use crate::db::fetch::{fetch_last_inbound, fetch_route_template};
+
+/// Free-form messages are only accepted by WhatsApp this long after the customer's last message.
+const WINDOW_HOURS: i64 = 24;
+
+const OUTSIDE_WINDOW_LOG: &str = "FORA DA JANELA DE 24H SEM TEMPLATE CONFIGURADO: RESPOSTA NÃO ENVIADA";
+
+/// The customer's latest inbound message, counting this click. In live mode the click is
+/// also recorded in `customer_activity`.
+pub async fn last_inbound(
+    db_client_control: &Object,
+    click: &ButtonClick,
+    mode: &Mode
+) -> Result<Option<DateTime<Utc>>, tokio_postgres::Error> {
+    let clicked_at = click.clicked_at();
+    match (mode, clicked_at) {
+        (Mode::Live, Some(clicked_at)) => {
+            let latest = crate::db::insert::upsert_customer_activity(db_client_control, &click.from, &click.source, clicked_at).await?;
+            Ok(Some(latest))
+        }
+        _ => {
+            let stored = fetch_last_inbound(db_client_control, &click.from).await?;
+            Ok(stored.max(clicked_at))
+        }
+    }
+}
+
+pub fn is_open(last_inbound: Option<DateTime<Utc>>) -> bool {
+    last_inbound.is_some_and(|at| Utc::now() - at < Duration::hours(WINDOW_HOURS))
+}
+
+/// The route's approved template with rendered params, if one is configured.
+pub async fn route_template(
+    db_client_control: &Object,
+    tenant_id: Option<&str>,
+    tipo: &str,
+    vars: &TemplateVars
+) -> Result<Option<OutboundMessage>, tokio_postgres::Error> {
+    let template = fetch_route_template(db_client_control, tenant_id, tipo).await?;
+    Ok(template.map(|template| OutboundMessage::Template {
+        params: template.params.iter().map(|param| render(param, vars)).collect(),
+        id: template.template_id,
+    }))
+}
+
+/// Picks what to send for a route: its reply inside the window, its template outside it.
+/// Without a template nothing is sent outside the window and the log message says why.
+pub fn outbound_for(
+    route: &mut Route,
+    last_inbound: Option<DateTime<Utc>>,
+    template: Option<OutboundMessage>
+) -> Option<OutboundMessage> {
+    let reply = route.reply.clone()?;
+    if is_open(last_inbound) {
+        return Some(OutboundMessage::Text(reply));
+    }
+
+    match template {
+        Some(template) => {
+            info!("Outside the 24h window (last inbound {:?}), sending the {} template instead", last_inbound, route.tipo);
+            route.log_message = format!("FORA DA JANELA DE 24H, TEMPLATE ENVIADO: {}", template.describe());
+            Some(template)
+        }
+        None => {
+            warn!("Outside the 24h window (last inbound {:?}) and no template for {}, not replying", last_inbound, route.tipo);
+            route.log_message = OUTSIDE_WINDOW_LOG.to_string();
+            None
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use deadpool_postgres::Object;
+use log::info;
+use serde::Serialize;
+use std::path::PathBuf;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+/// Where shadow mode writes what would have been sent and logged.
+#[derive(Debug, Clone)]
+pub enum Recorder {
+    Jsonl(PathBuf),
+    Table,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShadowRecord<'a> {
+    pub recorded_at: String,
+    pub num: &'a str,
+    pub source: &'a str,
+    pub source_name: &'a str,
+    pub button: &'a str,
+    pub tipo: &'a str,
+    pub reply: Option<&'a str>,
+    pub log_message: &'a str,
+}
+
+impl Recorder {
+    pub fn from_output(output: Option<&str>) -> Self {
+        match output {
+            Some(path) => Recorder::Jsonl(PathBuf::from(path)),
+            None => Recorder::Table,
+        }
+    }
+
+    pub async fn record(&self, record: &ShadowRecord<'_>, db_client_logs: &Object) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Recorder::Jsonl(path) => {
+                let mut line = serde_json::to_string(record)?;
+                line.push('\n');
+                let mut file = OpenOptions::new().create(true).append(true).open(path).await?;
+                file.write_all(line.as_bytes()).await?;
+                info!("Shadow record for {} appended to {}", record.num, path.display());
+            }
+            Recorder::Table => {
+                crate::db::insert::insert_shadow_log(db_client_logs, record).await?;
+                info!("Shadow record for {} stored in the logs database", record.num);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::config::EnvVars;
+use crate::db::cache::{SourceCache, TenantCache};
+use crate::db::log_writer::LogWriter;
+use crate::db::pools::Pools;
+use crate::rabbit::publish::Publisher;
+use crate::spool::spool::Spool;
+
+/// Long-lived state shared by every delivery, built once at startup.
+pub struct AppState {
+    pub env_vars: EnvVars,
+    pub pools: Pools,
+    pub sources: SourceCache,
+    pub tenants: TenantCache,
+    /// Publisher for `button_click.processed`, when `EVENTS_EXCHANGE` is set.
+    pub events: Option<Publisher>,
+    /// Logs waiting for the logs database to come back.
+    pub spool: Arc<Spool>,
+    pub log_writer: LogWriter,
+}
+
+impl AppState {
+    pub fn new(env_vars: EnvVars) -> Self {
+        let sources = SourceCache::new(
+            Duration::from_secs(env_vars.source_cache_ttl_secs),
+            Duration::from_secs(env_vars.source_cache_negative_ttl_secs),
+        );
+        let tenants = TenantCache::new(Duration::from_secs(env_vars.source_cache_ttl_secs));
+        let events = env_vars.events_exchange.as_ref().map(|_| Publisher::new(&env_vars.rabbit_url));
+        let spool = Arc::new(Spool::new(&env_vars.log_spool_path, env_vars.log_spool_max_bytes));
+        let log_writer = LogWriter::start(&env_vars, spool.clone());
+        AppState { env_vars, pools: Pools::default(), sources, tenants, events, spool, log_writer }
+    }
+
+    /// Called on every change notification from the source/tenant triggers.
+    pub fn invalidate_caches(&self) {
+        self.sources.invalidate_all();
+        self.tenants.invalidate_all();
+    }
+}
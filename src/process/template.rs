@@ -0,0 +1,133 @@
+use log::warn;
+use std::collections::HashMap;
+
+use super::clock::now_sao_paulo;
+use super::process::ButtonClick;
+use crate::db::fetch::SourceConnection;
+
+/// Longest customer-provided value substituted into a reply.
+const MAX_VALUE_CHARS: usize = 60;
+
+/// Values available to reply templates for one click.
+pub struct TemplateVars {
+    values: HashMap<&'static str, String>,
+}
+
+impl TemplateVars {
+    pub fn for_click(click: &ButtonClick, conn: &SourceConnection) -> Self {
+        let name = click.contact_name.as_deref().map(sanitize).unwrap_or_default();
+        let first_name = name.split_whitespace().next().unwrap_or_default().to_string();
+        let now = now_sao_paulo();
+
+        let values = HashMap::from([
+            ("nome", name),
+            ("primeiro_nome", first_name),
+            ("numero", sanitize(&click.from)),
+            ("source", conn.source.clone()),
+            ("source_name", conn.source_name.clone()),
+            ("uuid", conn.uuid.clone()),
+            ("data", now.format("%d/%m/%Y").to_string()),
+            ("hora", now.format("%H:%M").to_string()),
+        ]);
+        TemplateVars { values }
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str).filter(|value| !value.is_empty())
+    }
+}
+
+/// Renders `{{nome}}`-style placeholders. `{{primeiro_nome|cliente}}` falls back to `cliente`
+/// when the value is missing; without a fallback the placeholder renders empty. Substituted
+/// values are never rendered again, so a customer name cannot inject placeholders.
+pub fn render(template: &str, vars: &TemplateVars) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else { break };
+        output.push_str(&rest[..start]);
+
+        let placeholder = &rest[start + 2..start + 2 + len];
+        let (name, fallback) = match placeholder.split_once('|') {
+            Some((name, fallback)) => (name.trim(), Some(fallback.trim())),
+            None => (placeholder.trim(), None),
+        };
+        if !vars.values.contains_key(name) {
+            warn!("Unknown reply placeholder {{{{{}}}}}", name);
+        }
+        output.push_str(vars.get(name).or(fallback).unwrap_or_default());
+
+        rest = &rest[start + 2 + len + 2..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Customer-controlled values go into WhatsApp messages verbatim, so formatting markers
+/// and braces are dropped, control characters become spaces and the length is capped.
+fn sanitize(value: &str) -> String {
+    let cleaned: String = value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .filter(|c| !matches!(c, '*' | '_' | '~' | '`' | '{' | '}'))
+        .take(MAX_VALUE_CHARS)
+        .collect();
+    cleaned.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(name: &str) -> TemplateVars {
+        let name = sanitize(name);
+        let first_name = name.split_whitespace().next().unwrap_or_default().to_string();
+        TemplateVars {
+            values: HashMap::from([
+                ("nome", name),
+                ("primeiro_nome", first_name),
+                ("numero", "5511988887777".to_string()),
+            ]),
+        }
+    }
+
+    #[test]
+    fn renders_known_placeholders() {
+        assert_eq!(render("Olá, {{primeiro_nome}}! ({{ numero }})", &vars("Maria Silva")), "Olá, Maria! (5511988887777)");
+    }
+
+    #[test]
+    fn missing_value_uses_fallback_or_renders_empty() {
+        assert_eq!(render("Olá, {{primeiro_nome|cliente}}!", &vars("")), "Olá, cliente!");
+        assert_eq!(render("Olá, {{primeiro_nome}}!", &vars("")), "Olá, !");
+        assert_eq!(render("Olá, {{primeiro_nome|cliente}}!", &vars("Maria")), "Olá, Maria!");
+    }
+
+    #[test]
+    fn unknown_placeholder_renders_fallback_or_empty() {
+        assert_eq!(render("[{{protocolo}}]", &vars("Maria")), "[]");
+        assert_eq!(render("[{{protocolo|sem protocolo}}]", &vars("Maria")), "[sem protocolo]");
+    }
+
+    #[test]
+    fn unterminated_placeholder_is_kept_verbatim() {
+        assert_eq!(render("Olá, {{primeiro_nome}} {{nome", &vars("Maria")), "Olá, Maria {{nome");
+        assert_eq!(render("Olá {{", &vars("Maria")), "Olá {{");
+    }
+
+    #[test]
+    fn name_cannot_inject_placeholders_or_formatting() {
+        let vars = vars("*Maria* {{numero}} _Silva_");
+        assert_eq!(vars.get("nome"), Some("Maria numero Silva"));
+        assert_eq!(render("Olá, {{nome}}!", &vars), "Olá, Maria numero Silva!");
+    }
+
+    #[test]
+    fn sanitize_replaces_control_characters_and_caps_length() {
+        assert_eq!(sanitize("Maria\n\tSilva\u{7}"), "Maria Silva");
+        assert_eq!(sanitize("Maria\u{0}Silva"), "Maria Silva");
+        assert_eq!(sanitize(&"a".repeat(100)).chars().count(), MAX_VALUE_CHARS);
+    }
+}
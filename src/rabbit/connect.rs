@@ -1,63 +1,186 @@
 use lapin::{
     options::{BasicConsumeOptions, BasicQosOptions, QueueDeclareOptions},
     types::FieldTable,
-    ConnectionProperties, Consumer, Connection
+    ConnectionProperties, Consumer, Connection, Event
 };
-use log::{info, error};
+use futures::stream::{self, SelectAll};
+use futures::StreamExt;
+use log::{info, error, warn};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use tokio::time::sleep;
 
-pub async fn connect_rabbitmq(rabbit_url: &str) -> Result<Connection, lapin::Error> {
+static RECONNECTS: AtomicU64 = AtomicU64::new(0);
+
+pub struct RabbitSession {
+    pub connection: Connection,
+    pub consumer: SelectAll<Consumer>,
+    pub errors: UnboundedReceiver<lapin::Error>,
+}
+
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff { initial, max, current: initial }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+pub fn record_reconnect() -> u64 {
+    RECONNECTS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+pub fn reconnect_count() -> u64 {
+    RECONNECTS.load(Ordering::Relaxed)
+}
+
+pub async fn connect_rabbitmq(rabbit_url: &str, errors: UnboundedSender<lapin::Error>) -> Result<Connection, lapin::Error> {
     let options = ConnectionProperties::default()
         .with_connection_name("button_consumer".into());
-    Connection::connect(rabbit_url, options).await
+    let connection = Connection::connect(rabbit_url, options).await?;
+
+    // Connection and channel errors both arrive as events on the connection.
+    let mut events = Box::pin(connection.events_listener());
+    tokio::spawn(async move {
+        while let Some(event) = events.next().await {
+            if let Event::Error(e) = event {
+                error!("RabbitMQ connection error: {}", e);
+                if errors.send(e).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(connection)
 }
 
-pub async fn setup_consumer(connection: &Connection) -> Result<Consumer, lapin::Error> {
+pub async fn setup_consumer(
+    connection: &Connection,
+    queues: &[String]
+) -> Result<SelectAll<Consumer>, lapin::Error> {
     let channel = connection.create_channel().await?;
-    
+
     channel.basic_qos(1, BasicQosOptions::default()).await?;
-    
+
     let queue_options = QueueDeclareOptions {
         passive: true,
         durable: true,
         ..QueueDeclareOptions::default()
     };
-    
-    channel.queue_declare("button_templates", queue_options, FieldTable::default()).await?;
-    
-    let consumer = channel
-        .basic_consume(
-            "button_templates",
-            "button-consumer",
-            BasicConsumeOptions::default(),
-            FieldTable::default(),
-        )
-        .await?;
-    
-    Ok(consumer)
-}
-
-pub async fn create_rabbitmq_consumer(rabbit_url: &str) -> Option<(Consumer, Connection)> {
+
+    let mut consumers = Vec::with_capacity(queues.len());
+    for queue in queues {
+        channel.queue_declare(queue, queue_options, FieldTable::default()).await?;
+
+        let consumer = channel
+            .basic_consume(
+                queue,
+                &format!("button-consumer-{}", queue),
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        info!("Consuming from queue {}", queue);
+        consumers.push(consumer);
+    }
+
+    Ok(stream::select_all(consumers))
+}
+
+/// Declares a durable queue this service publishes to, creating it when missing.
+pub async fn declare_queue(connection: &Connection, queue: &str) -> Result<(), lapin::Error> {
+    let channel = connection.create_channel().await?;
+    let options = QueueDeclareOptions { durable: true, ..QueueDeclareOptions::default() };
+    channel.queue_declare(queue, options, FieldTable::default()).await?;
+    let _ = channel.close(200, "Queue declared").await;
+    info!("Queue {} declared", queue);
+    Ok(())
+}
+
+/// Connects and sets up the consumer, retrying with `backoff`. Every failed attempt counts as
+/// a reconnect. Returns `None` when `shutdown` completes while waiting to retry.
+pub async fn create_rabbitmq_consumer(
+    rabbit_url: &str,
+    queues: &[String],
+    backoff: &mut Backoff,
+    shutdown: impl Future<Output = ()>
+) -> Option<RabbitSession> {
+    tokio::pin!(shutdown);
     loop {
-        match connect_rabbitmq(rabbit_url).await {
+        let (errors_tx, errors) = mpsc::unbounded_channel();
+        match connect_rabbitmq(rabbit_url, errors_tx).await {
             Ok(connection) => {
                 info!("RabbitMQ connection established");
-                match setup_consumer(&connection).await {
+                match setup_consumer(&connection, queues).await {
                     Ok(consumer) => {
-                        info!("Consumer set up successfully");
-                        return Some((consumer, connection));
+                        info!("Consumer set up successfully on {} queue(s)", queues.len());
+                        backoff.reset();
+                        return Some(RabbitSession { connection, consumer, errors });
                     }
                     Err(e) => {
                         error!("Failed to set up consumer: {}", e);
-                        sleep(Duration::from_secs(5)).await;
+                        let _ = connection.close(200, "Consumer setup failed").await;
                     }
                 }
             }
-            Err(e) => {
-                error!("Failed to connect to RabbitMQ: {}", e);
-                sleep(Duration::from_secs(5)).await;
+            Err(e) => error!("Failed to connect to RabbitMQ: {}", e),
+        }
+
+        let reconnects = record_reconnect();
+        let delay = backoff.next_delay();
+        warn!("Retrying RabbitMQ in {:?} (reconnect #{})", delay, reconnects);
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = &mut shutdown => {
+                info!("Shutdown requested while reconnecting to RabbitMQ");
+                return None;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        let delays: Vec<u64> = (0..6).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn reset_starts_over_from_the_initial_delay() {
+        let mut backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(60));
+        backoff.next_delay();
+        backoff.next_delay();
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn initial_delay_above_the_cap_is_used_once() {
+        let mut backoff = Backoff::new(Duration::from_secs(30), Duration::from_secs(10));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(30));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(10));
+    }
+}
@@ -1 +1,2 @@
-pub mod connect;
\ No newline at end of file
+pub mod connect;
+pub mod publish;
\ No newline at end of file
@@ -0,0 +1,75 @@
+use lapin::{
+    options::{BasicPublishOptions, ConfirmSelectOptions},
+    BasicProperties, Channel, Connection
+};
+use log::{info, error, warn};
+use tokio::sync::{mpsc, Mutex};
+
+use super::connect::connect_rabbitmq;
+
+/// A lazily connected publisher with publisher confirms. The channel is opened on first use
+/// and dropped on any error so the next publish reconnects. Messages are published
+/// `mandatory`, so one the broker cannot route (e.g. to a queue that does not exist) is an
+/// error rather than silently dropped.
+pub struct Publisher {
+    rabbit_url: String,
+    session: Mutex<Option<(Connection, Channel)>>,
+}
+
+impl Publisher {
+    pub fn new(rabbit_url: &str) -> Self {
+        Publisher {
+            rabbit_url: rabbit_url.to_string(),
+            session: Mutex::new(None),
+        }
+    }
+
+    async fn open(&self) -> Result<(Connection, Channel), lapin::Error> {
+        let (errors_tx, _errors) = mpsc::unbounded_channel();
+        let connection = connect_rabbitmq(&self.rabbit_url, errors_tx).await?;
+        let channel = connection.create_channel().await?;
+        channel.confirm_select(ConfirmSelectOptions::default()).await?;
+        info!("RabbitMQ publisher channel opened");
+        Ok((connection, channel))
+    }
+
+    pub async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        body: &[u8],
+        properties: BasicProperties
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut session = self.session.lock().await;
+        if session.is_none() {
+            *session = Some(self.open().await?);
+        }
+        let (_, channel) = session.as_ref().expect("publisher session was just opened");
+
+        let result = async {
+            channel
+                .basic_publish(exchange, routing_key, BasicPublishOptions { mandatory: true, ..BasicPublishOptions::default() }, body, properties)
+                .await?
+                .await
+        }.await;
+
+        match result {
+            Ok(confirmation) if confirmation.is_ack() => match confirmation.take_message() {
+                None => Ok(()),
+                Some(returned) => {
+                    warn!("Message to {}/{} was returned as unroutable: {}", exchange, routing_key, returned.reply_text);
+                    Err(format!("Message to {}/{} could not be routed: {}", exchange, routing_key, returned.reply_text).into())
+                }
+            },
+            Ok(_) => {
+                warn!("Broker did not confirm message published to {}/{}", exchange, routing_key);
+                Err(format!("Message to {}/{} was not confirmed by the broker", exchange, routing_key).into())
+            }
+            Err(e) => {
+                error!("Failed to publish to {}/{}: {}", exchange, routing_key, e);
+                *session = None;
+                Err(Box::new(e))
+            }
+        }
+    }
+}
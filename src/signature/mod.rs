@@ -0,0 +1 @@
+pub mod signature;
\ No newline at end of file
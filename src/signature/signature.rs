@@ -0,0 +1,72 @@
+use hmac::{Hmac, Mac};
+use lapin::types::{AMQPValue, FieldTable};
+use sha2::Sha256;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::config::EnvVars;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header carrying our own HMAC of the message body.
+pub const SIGNATURE_HEADER: &str = "x-signature";
+/// Header carrying the provider's original `X-Hub-Signature-256`, forwarded as-is.
+pub const PROVIDER_SIGNATURE_HEADER: &str = "x-hub-signature-256";
+
+static QUARANTINED: AtomicU64 = AtomicU64::new(0);
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Checks a hex HMAC-SHA256 signature, with or without the `sha256=` prefix Meta uses
+/// in `X-Hub-Signature-256`. The comparison is constant-time.
+pub fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = signature.trim();
+    let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+    let expected = match hex::decode(signature) {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// Compares a shared token in constant time.
+pub fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+pub fn header_value(headers: Option<&FieldTable>, name: &str) -> Option<String> {
+    match headers?.inner().get(name)? {
+        AMQPValue::LongString(value) => Some(value.to_string()),
+        AMQPValue::ShortString(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Verifies a consumed message when `AMQP_SIGNING_SECRET` is configured. A message is accepted
+/// with a valid `x-signature`, or with a valid forwarded provider signature when `META_APP_SECRET` is set.
+pub fn verify_delivery(env_vars: &EnvVars, body: &[u8], headers: Option<&FieldTable>) -> Result<(), &'static str> {
+    let Some(secret) = &env_vars.amqp_signing_secret else {
+        return Ok(());
+    };
+
+    if let Some(signature) = header_value(headers, SIGNATURE_HEADER) {
+        return if verify(secret, body, &signature) { Ok(()) } else { Err("invalid x-signature") };
+    }
+
+    if let (Some(app_secret), Some(signature)) = (&env_vars.meta_app_secret, header_value(headers, PROVIDER_SIGNATURE_HEADER)) {
+        return if verify(app_secret, body, &signature) { Ok(()) } else { Err("invalid forwarded provider signature") };
+    }
+
+    Err("missing signature header")
+}
+
+pub fn record_quarantine() -> u64 {
+    QUARANTINED.fetch_add(1, Ordering::Relaxed) + 1
+}
@@ -0,0 +1 @@
+pub mod spool;
\ No newline at end of file
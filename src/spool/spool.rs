@@ -0,0 +1,147 @@
+use log::{info, error, warn};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::db::log_writer::LogRecord;
+use crate::process::state::AppState;
+
+/// Append-only JSONL file of logs waiting for the logs database. The drain renames it to
+/// `<path>.draining` first, so appends never race with a drain in progress.
+pub struct Spool {
+    path: PathBuf,
+    max_bytes: u64,
+    lock: Mutex<()>,
+}
+
+impl Spool {
+    pub fn new(path: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        Spool { path: path.into(), max_bytes, lock: Mutex::new(()) }
+    }
+
+    fn draining_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".draining");
+        PathBuf::from(path)
+    }
+
+    /// Appends and fsyncs one log. Fails once the file would grow past `LOG_SPOOL_MAX_BYTES`.
+    pub async fn append(&self, log: &LogRecord) -> Result<(), Box<dyn std::error::Error>> {
+        let line = line(log)?;
+        let _guard = self.lock.lock().await;
+        self.ensure_room(&line).await?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        file.write_all(line.as_bytes()).await?;
+        file.sync_data().await?;
+        warn!("Log for {} spooled to {}", log.num, self.path.display());
+        Ok(())
+    }
+
+    /// Fails if `log` would not fit, so a caller can find out before doing something it cannot
+    /// take back. Another append may still take the room in between.
+    pub async fn check_room(&self, log: &LogRecord) -> Result<(), Box<dyn std::error::Error>> {
+        let line = line(log)?;
+        let _guard = self.lock.lock().await;
+        self.ensure_room(&line).await
+    }
+
+    async fn ensure_room(&self, line: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let size = fs::metadata(&self.path).await.map(|m| m.len()).unwrap_or(0);
+        if size + line.len() as u64 > self.max_bytes {
+            return Err(format!("log spool {} is full ({} bytes)", self.path.display(), size).into());
+        }
+        Ok(())
+    }
+
+    /// Writes the spooled logs back to their logs databases. Stops at the first failure and
+    /// keeps the rest for the next run. Returns the number of logs written.
+    pub async fn drain(&self, state: &AppState) -> Result<usize, Box<dyn std::error::Error>> {
+        let draining = self.draining_path();
+        {
+            let _guard = self.lock.lock().await;
+            if !fs::try_exists(&draining).await? {
+                if !fs::try_exists(&self.path).await? {
+                    return Ok(0);
+                }
+                fs::rename(&self.path, &draining).await?;
+            }
+        }
+
+        let content = fs::read_to_string(&draining).await?;
+        let lines: Vec<&str> = content.lines().filter(|line| !line.trim().is_empty()).collect();
+        info!("Draining {} spooled log(s) from {}", lines.len(), draining.display());
+
+        let mut drained = 0;
+        for (i, line) in lines.iter().enumerate() {
+            let log: LogRecord = match serde_json::from_str(line) {
+                Ok(log) => log,
+                Err(e) => {
+                    // Most likely the last line of a write cut short by a crash.
+                    error!("Dropping unreadable spooled log: {}", e);
+                    continue;
+                }
+            };
+            let written = write(state, &log).await.map_err(|e| e.to_string());
+            if let Err(e) = written {
+                warn!("Logs database still unavailable, {} spooled log(s) left: {}", lines.len() - i, e);
+                rewrite(&draining, &lines[i..]).await?;
+                return Ok(drained);
+            }
+            drained += 1;
+        }
+
+        fs::remove_file(&draining).await?;
+        Ok(drained)
+    }
+}
+
+fn line(log: &LogRecord) -> Result<String, serde_json::Error> {
+    let mut line = serde_json::to_string(log)?;
+    line.push('\n');
+    Ok(line)
+}
+
+async fn write(state: &AppState, log: &LogRecord) -> Result<(), Box<dyn std::error::Error>> {
+    let db_client_control = state.pools.client(&state.env_vars.db_url_logs).await?;
+    let tenant = match &log.tenant_id {
+        Some(id) => state.tenants.get(&db_client_control, id).await?,
+        None => None,
+    };
+    let db_client_logs = match tenant.and_then(|t| t.logs_db_url) {
+        Some(url) => state.pools.client(&url).await?,
+        None => db_client_control,
+    };
+    crate::db::insert::insert_spooled_log(&db_client_logs, log).await?;
+    Ok(())
+}
+
+/// Replaces the draining file with the logs not written yet.
+async fn rewrite(path: &Path, lines: &[&str]) -> Result<(), std::io::Error> {
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut file = fs::File::create(&tmp).await?;
+    for line in lines {
+        file.write_all(line.as_bytes()).await?;
+        file.write_all(b"\n").await?;
+    }
+    file.sync_all().await?;
+    fs::rename(&tmp, path).await
+}
+
+/// Drains the spool every `SCHEDULER_INTERVAL_SECS`. Runs for the life of the process.
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(state.env_vars.scheduler_interval_secs));
+    loop {
+        interval.tick().await;
+        match state.spool.drain(&state).await {
+            Ok(0) => {}
+            Ok(drained) => info!("Wrote {} spooled log(s) to the logs database", drained),
+            Err(e) => error!("Spool drain failed: {}", e),
+        }
+    }
+}